@@ -0,0 +1,315 @@
+use crate::cell::pixel_intensity;
+use crate::luminance::Luminance;
+use clap::ValueEnum;
+use image::RgbaImage;
+
+const LEVELS: usize = 256;
+
+// share of the darkest and brightest pixels auto levels clips, so a few outliers don't
+// keep the range from being stretched
+const LEVELS_CLIP: f64 = 0.005;
+
+// clahe splits the image into a grid of tiles that are equalized on their own
+const CLAHE_TILES: u32 = 4;
+
+// how many times the average count a histogram bin may hold before clahe clips it, this
+// limits how much noise in flat areas gets amplified
+const CLAHE_CLIP: f64 = 3.0;
+
+/// Histogram equalization spreading intensities evenly over the whole range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Equalize {
+    /// Equalize the histogram of the whole image
+    Global,
+    /// Contrast limited adaptive equalization, equalizes regions of the image separately
+    Clahe,
+}
+
+/// Tone adjustments applied to the scaled image before it's converted into characters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adjustments {
+    /// Offset added to every channel, -1.0 - 1.0
+    pub brightness: f64,
+    /// Factor the distance of every channel from mid grey is scaled by
+    pub contrast: f64,
+    /// Gamma correction, values above 1.0 brighten the mid tones
+    pub gamma: f64,
+    /// Stretch the range of intensities to cover black to white
+    pub auto_levels: bool,
+    /// Histogram equalization
+    pub equalize: Option<Equalize>,
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        return Adjustments {
+            brightness: 0.0,
+            contrast: 1.0,
+            gamma: 1.0,
+            auto_levels: false,
+            equalize: None,
+        };
+    }
+}
+
+impl Adjustments {
+    /// Returns true if the adjustments leave the image unchanged
+    pub fn is_identity(&self) -> bool {
+        return *self == Adjustments::default();
+    }
+}
+
+type Curve = [u8; LEVELS];
+
+/// Returns a curve mapping every level to itself
+fn identity() -> Curve {
+    let mut curve: Curve = [0; LEVELS];
+    for (i, level) in curve.iter_mut().enumerate() {
+        *level = i as u8;
+    }
+    return curve;
+}
+
+/// Returns the intensity histograms of a grid of tiles laid over the frames, in row-major
+/// order. Transparent pixels are left out.
+///
+/// # Arguments
+///
+/// * 'frames'      - Scaled frames
+/// * 'curve'       - Curve already applied to the intensities
+/// * 'luminance'   - Model used to compute intensities
+/// * 'tiles'       - Number of tiles along each side of the frames
+fn histograms(frames: &[&RgbaImage], curve: &Curve, luminance: Luminance, tiles: u32) -> Vec<[f64; LEVELS]> {
+    let mut histograms: Vec<[f64; LEVELS]> = vec![[0.0; LEVELS]; (tiles * tiles) as usize];
+    for frame in frames {
+        let (width, height) = frame.dimensions();
+        for (x, y, pixel) in frame.enumerate_pixels() {
+            if pixel[3] == 0 {
+                continue;
+            }
+            let intensity: usize = pixel_intensity(pixel, luminance).round().clamp(0.0, 255.0) as usize;
+            let tile: u32 = (y * tiles / height) * tiles + x * tiles / width;
+            histograms[tile as usize][curve[intensity] as usize] += 1.0;
+        }
+    }
+    return histograms;
+}
+
+/// Returns the curve stretching the histogram's range of levels over the whole range
+///
+/// # Arguments
+///
+/// * 'histogram'   - Intensity histogram
+fn levels_curve(histogram: &[f64; LEVELS]) -> Curve {
+    let total: f64 = histogram.iter().sum();
+    let (mut low, mut high, mut count): (usize, usize, f64) = (0, LEVELS - 1, 0.0);
+    for (level, bin) in histogram.iter().enumerate() {
+        if count <= total * LEVELS_CLIP {
+            low = level;
+        }
+        count += bin;
+        if count < total * (1.0 - LEVELS_CLIP) {
+            high = level + 1;
+        }
+    }
+    if high <= low {
+        return identity();
+    }
+    let mut curve: Curve = [0; LEVELS];
+    for (i, level) in curve.iter_mut().enumerate() {
+        let stretched: f64 = (i as f64 - low as f64) * 255.0 / (high - low) as f64;
+        *level = stretched.round().clamp(0.0, 255.0) as u8;
+    }
+    return curve;
+}
+
+/// Returns the curve equalizing a histogram
+///
+/// # Arguments
+///
+/// * 'histogram'   - Intensity histogram
+/// * 'clip'        - Limit on the count of a bin, as a multiple of the average count
+fn equalize_curve(histogram: &[f64; LEVELS], clip: Option<f64>) -> Curve {
+    let total: f64 = histogram.iter().sum();
+    if total == 0.0 {
+        return identity();
+    }
+    let mut bins: [f64; LEVELS] = *histogram;
+    if let Some(clip) = clip {
+        // clip the tall bins and spread what was cut off evenly over every bin
+        let limit: f64 = (clip * total / LEVELS as f64).max(1.0);
+        let excess: f64 = bins.iter().map(|bin| (bin - limit).max(0.0)).sum();
+        for bin in bins.iter_mut() {
+            *bin = bin.min(limit) + excess / LEVELS as f64;
+        }
+    }
+    let first: f64 = bins.iter().copied().find(|bin| *bin > 0.0).unwrap_or(0.0);
+    let mut curve: Curve = [0; LEVELS];
+    let mut cumulative: f64 = 0.0;
+    for (level, bin) in curve.iter_mut().zip(bins) {
+        cumulative += bin;
+        let equalized: f64 = match total > first {
+            true => (cumulative - first) / (total - first) * 255.0,
+            false => 255.0,
+        };
+        *level = equalized.round().clamp(0.0, 255.0) as u8;
+    }
+    return curve;
+}
+
+/// Returns the curve applying the brightness, contrast and gamma adjustments
+///
+/// # Arguments
+///
+/// * 'adjust'      - Tone adjustments
+fn tone_curve(adjust: &Adjustments) -> Curve {
+    let mut curve: Curve = [0; LEVELS];
+    for (i, level) in curve.iter_mut().enumerate() {
+        let mut value: f64 = i as f64 / 255.0 + adjust.brightness;
+        value = ((value - 0.5) * adjust.contrast + 0.5).clamp(0.0, 1.0);
+        value = value.powf(1.0 / adjust.gamma);
+        *level = (value * 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+    return curve;
+}
+
+/// Tone curves applied to every frame of an image. The curves are built once from the
+/// statistics of all frames, so the frames of an animation are adjusted alike and don't flicker.
+#[derive(Debug, Clone)]
+pub struct ToneMap {
+    // auto levels and global equalization
+    levels: Curve,
+    // clahe curves of every tile in row-major order
+    tiles: Option<Vec<Curve>>,
+    // brightness, contrast and gamma
+    tone: Curve,
+}
+
+impl ToneMap {
+    /// Builds the tone curves for a set of frames
+    ///
+    /// # Arguments
+    ///
+    /// * 'frames'      - Scaled frames
+    /// * 'adjust'      - Tone adjustments
+    /// * 'luminance'   - Model used to compute intensities
+    pub fn new(frames: &[&RgbaImage], adjust: &Adjustments, luminance: Luminance) -> ToneMap {
+        let mut levels: Curve = identity();
+        if adjust.auto_levels {
+            levels = levels_curve(&histograms(frames, &levels, luminance, 1)[0]);
+        }
+        if adjust.equalize == Some(Equalize::Global) {
+            let equalized: Curve = equalize_curve(&histograms(frames, &levels, luminance, 1)[0], None);
+            levels = levels.map(|level| equalized[level as usize]);
+        }
+        let tiles: Option<Vec<Curve>> = match adjust.equalize {
+            Some(Equalize::Clahe) => Some(
+                histograms(frames, &levels, luminance, CLAHE_TILES)
+                    .iter()
+                    .map(|histogram| equalize_curve(histogram, Some(CLAHE_CLIP)))
+                    .collect(),
+            ),
+            _ => None,
+        };
+        return ToneMap { levels, tiles, tone: tone_curve(adjust) };
+    }
+
+    /// Returns a channel value mapped by the clahe curves, interpolating between the curves
+    /// of the four tiles nearest to the position
+    ///
+    /// # Arguments
+    ///
+    /// * 'tiles'       - Clahe curves
+    /// * 'position'    - Position in the frame, 0.0 - 1.0 along each side
+    /// * 'value'       - Channel value
+    fn local(tiles: &[Curve], position: (f64, f64), value: u8) -> u8 {
+        // measured from the center of the first tile
+        let tile_x: f64 = (position.0 * CLAHE_TILES as f64 - 0.5).clamp(0.0, (CLAHE_TILES - 1) as f64);
+        let tile_y: f64 = (position.1 * CLAHE_TILES as f64 - 0.5).clamp(0.0, (CLAHE_TILES - 1) as f64);
+        let (x0, y0) = (tile_x.floor() as u32, tile_y.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(CLAHE_TILES - 1), (y0 + 1).min(CLAHE_TILES - 1));
+        let (wx, wy) = (tile_x - x0 as f64, tile_y - y0 as f64);
+        let at = |x: u32, y: u32| -> f64 { return tiles[(y * CLAHE_TILES + x) as usize][value as usize] as f64 };
+        let top: f64 = at(x0, y0) * (1.0 - wx) + at(x1, y0) * wx;
+        let bottom: f64 = at(x0, y1) * (1.0 - wx) + at(x1, y1) * wx;
+        return (top * (1.0 - wy) + bottom * wy).round() as u8;
+    }
+
+    /// Applies the tone curves to the color channels of an image, which may have any resolution
+    ///
+    /// # Arguments
+    ///
+    /// * 'img'     - Scaled frame or the full resolution image it was scaled from
+    pub fn apply(&self, img: &mut RgbaImage) {
+        let (width, height) = img.dimensions();
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            let position: (f64, f64) = ((x as f64 + 0.5) / width as f64, (y as f64 + 0.5) / height as f64);
+            for channel in pixel.0.iter_mut().take(3) {
+                let mut value: u8 = self.levels[*channel as usize];
+                if let Some(tiles) = &self.tiles {
+                    value = ToneMap::local(tiles, position, value);
+                }
+                *channel = self.tone[value as usize];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use image::Rgba;
+
+    /// Returns a dark horizontal gradient
+    fn dark_gradient() -> RgbaImage {
+        return RgbaImage::from_fn(64, 16, |x, _| {
+            let value: u8 = 10 + x as u8;
+            return Rgba([value, value, value, 255]);
+        });
+    }
+
+    #[test]
+    fn auto_levels_and_equalize_stretch_range() {
+        // the gradient covers 10 - 73, clahe's clip limit keeps it from stretching as far
+        for (adjust, spread) in [
+            (Adjustments { auto_levels: true, ..Adjustments::default() }, 230),
+            (Adjustments { equalize: Some(Equalize::Global), ..Adjustments::default() }, 230),
+            (Adjustments { equalize: Some(Equalize::Clahe), ..Adjustments::default() }, 100),
+        ] {
+            let mut img: RgbaImage = dark_gradient();
+            ToneMap::new(&[&img.clone()], &adjust, Luminance::Rec709).apply(&mut img);
+            let values: Vec<u8> = img.pixels().map(|pixel| pixel[0]).collect();
+            let (min, max) = (*values.iter().min().unwrap(), *values.iter().max().unwrap());
+            assert!(max - min > spread, "{:?} {} {}", adjust, min, max);
+        }
+    }
+
+    #[test]
+    fn brightness_contrast_gamma() {
+        let curve: Curve = tone_curve(&Adjustments::default());
+        assert_eq!(identity(), curve);
+        let curve: Curve = tone_curve(&Adjustments { brightness: 0.5, ..Adjustments::default() });
+        assert_eq!(128, curve[0]);
+        let curve: Curve = tone_curve(&Adjustments { contrast: 2.0, ..Adjustments::default() });
+        assert_eq!((0, 255), (curve[64], curve[192]));
+        let curve: Curve = tone_curve(&Adjustments { gamma: 2.0, ..Adjustments::default() });
+        assert!(curve[64] > 120);
+    }
+
+    #[test]
+    fn frames_share_statistics() {
+        // a dark and a bright frame are mapped by the same curves, equalizing each frame on its
+        // own would stretch both over the whole range
+        let dark: RgbaImage = dark_gradient();
+        let bright: RgbaImage = RgbaImage::from_fn(64, 16, |x, _| Rgba([150 + x as u8, 150 + x as u8, 150 + x as u8, 255]));
+        let adjust: Adjustments = Adjustments { equalize: Some(Equalize::Clahe), ..Adjustments::default() };
+        let tone: ToneMap = ToneMap::new(&[&dark, &bright], &adjust, Luminance::Rec709);
+        let mut darkened: RgbaImage = dark.clone();
+        tone.apply(&mut darkened);
+        let mut brightened: RgbaImage = bright.clone();
+        tone.apply(&mut brightened);
+        // the bright frame stays brighter than the dark one
+        assert!(brightened.get_pixel(0, 0)[0] > darkened.get_pixel(63, 0)[0]);
+    }
+}
@@ -0,0 +1,456 @@
+use crate::cell::{reduce_cells, Cell, CellGlyph, CellStat};
+use crate::dither::{dither_levels, Dither};
+use crate::luminance::Luminance;
+use clap::ValueEnum;
+use image::RgbaImage;
+
+const MAX_VALUE: f64 = 255.0;
+
+// first character of the unicode braille block, the low byte holds one bit per dot
+const BRAILLE_BASE: u32 = 0x2800;
+
+// braille dot bits in row-major order of the 2x4 grid, dots 7 and 8 were added below the
+// original six dot cell
+const BRAILLE_DOTS: [u32; 8] = [0x01, 0x08, 0x02, 0x10, 0x04, 0x20, 0x40, 0x80];
+
+// quadrant characters indexed by their row-major mask
+const QUADRANTS: [char; 16] = [
+    ' ', '\u{2598}', '\u{259D}', '\u{2580}', '\u{2596}', '\u{258C}', '\u{259E}', '\u{259B}',
+    '\u{2597}', '\u{259A}', '\u{2590}', '\u{259C}', '\u{2584}', '\u{2599}', '\u{259F}', '\u{2588}',
+];
+
+// first sextant of the unicode 13 legacy computing block, sextants are ordered by their
+// row-major mask leaving out the masks that already had a character (left and right half)
+const SEXTANT_BASE: u32 = 0x1FB00;
+const SEXTANT_LEFT: u32 = 0b010101;
+const SEXTANT_RIGHT: u32 = 0b101010;
+const SEXTANT_FULL: u32 = 0b111111;
+
+/// Unicode block characters used instead of ascii characters, each character shows a grid
+/// of sub-pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BlockMode {
+    /// Upper and lower half blocks, two vertically stacked pixels per character
+    Half,
+    /// Quadrant blocks, a 2x2 grid of pixels per character
+    Quadrant,
+    /// Sextant blocks (unicode 13), a 2x3 grid of pixels per character
+    Sextant,
+    /// Braille patterns, a 2x4 grid of dots per character
+    Braille,
+}
+
+/// How a block character is drawn on saved output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockShape {
+    /// Number of sub-pixel columns and rows
+    pub grid: (u32, u32),
+    /// Filled sub-pixels in row-major order starting at the top left
+    pub mask: u32,
+    /// Whether sub-pixels are drawn as dots rather than filling their whole area
+    pub dots: bool,
+}
+
+impl BlockMode {
+    /// Returns the number of sub-pixel columns and rows each character shows
+    pub fn grid(&self) -> (u32, u32) {
+        match self {
+            BlockMode::Half => return (1, 2),
+            BlockMode::Quadrant => return (2, 2),
+            BlockMode::Sextant => return (2, 3),
+            BlockMode::Braille => return (2, 4),
+        };
+    }
+
+    /// Whether the sub-pixels of a colored character are split into a foreground and a
+    /// background color, braille dots are too small to show a background between them
+    pub fn two_tone(&self) -> bool {
+        return *self != BlockMode::Braille;
+    }
+
+    /// Returns the character showing the given sub-pixels, bits are set for filled
+    /// sub-pixels in row-major order starting at the top left
+    ///
+    /// # Arguments
+    ///
+    /// * 'mask'      - Filled sub-pixels
+    pub fn glyph(&self, mask: u32) -> char {
+        match self {
+            BlockMode::Half => match mask {
+                0b00 => return ' ',
+                0b01 => return '\u{2580}',
+                0b10 => return '\u{2584}',
+                _ => return '\u{2588}',
+            },
+            BlockMode::Quadrant => return QUADRANTS[(mask & 0b1111) as usize],
+            BlockMode::Sextant => match mask & SEXTANT_FULL {
+                0 => return ' ',
+                SEXTANT_LEFT => return '\u{258C}',
+                SEXTANT_RIGHT => return '\u{2590}',
+                SEXTANT_FULL => return '\u{2588}',
+                mask => {
+                    let skipped: u32 = (mask > SEXTANT_LEFT) as u32 + (mask > SEXTANT_RIGHT) as u32;
+                    return char::from_u32(SEXTANT_BASE + mask - 1 - skipped).unwrap_or(' ');
+                }
+            },
+            BlockMode::Braille => {
+                let mut bits: u32 = 0;
+                for (i, dot) in BRAILLE_DOTS.iter().enumerate() {
+                    if mask & (1 << i) != 0 {
+                        bits |= dot;
+                    }
+                }
+                return char::from_u32(BRAILLE_BASE + bits).unwrap_or(' ');
+            }
+        };
+    }
+}
+
+/// Returns the sub-pixel grid and mask of filled sub-pixels of a block character, None for
+/// any other character
+///
+/// # Arguments
+///
+/// * 'token'     - Character to look up
+pub fn block_shape(token: char) -> Option<BlockShape> {
+    let (grid, mask, dots): ((u32, u32), u32, bool) = match token {
+        '\u{2580}' => ((1, 2), 0b01, false),
+        '\u{2584}' => ((1, 2), 0b10, false),
+        '\u{2588}' => ((1, 1), 0b1, false),
+        '\u{258C}' => ((2, 1), 0b01, false),
+        '\u{2590}' => ((2, 1), 0b10, false),
+        '\u{2800}'..='\u{28FF}' => {
+            let bits: u32 = token as u32 - BRAILLE_BASE;
+            let mut mask: u32 = 0;
+            for (i, dot) in BRAILLE_DOTS.iter().enumerate() {
+                if bits & dot != 0 {
+                    mask |= 1 << i;
+                }
+            }
+            ((2, 4), mask, true)
+        }
+        '\u{1FB00}'..='\u{1FB3B}' => {
+            let mut mask: u32 = token as u32 - SEXTANT_BASE + 1;
+            if mask >= SEXTANT_LEFT {
+                mask += 1;
+            }
+            if mask >= SEXTANT_RIGHT {
+                mask += 1;
+            }
+            ((2, 3), mask, false)
+        }
+        _ => match QUADRANTS[1..].iter().position(|quadrant| *quadrant == token) {
+            Some(index) => ((2, 2), index as u32 + 1, false),
+            None => return None,
+        },
+    };
+    return Some(BlockShape { grid, mask, dots });
+}
+
+/// Returns the average color of a group of cells
+///
+/// # Arguments
+///
+/// * 'cells'     - Cells to average
+fn mean_rgb(cells: &[&Cell]) -> (u8, u8, u8) {
+    if cells.is_empty() {
+        return (0, 0, 0);
+    }
+    let mut sum: [f64; 3] = [0.0; 3];
+    for cell in cells {
+        sum[0] += cell.rgb.0 as f64;
+        sum[1] += cell.rgb.1 as f64;
+        sum[2] += cell.rgb.2 as f64;
+    }
+    let rgb: [u8; 3] = sum.map(|channel| (channel / cells.len() as f64).round() as u8);
+    return (rgb[0], rgb[1], rgb[2]);
+}
+
+/// Returns the summed squared rgb distance of a group of cells to their average color
+///
+/// # Arguments
+///
+/// * 'cells'     - Cells in the group
+fn split_error(cells: &[&Cell]) -> f64 {
+    let (r, g, b) = mean_rgb(cells);
+    return cells
+        .iter()
+        .map(|cell| {
+            let dr: f64 = cell.rgb.0 as f64 - r as f64;
+            let dg: f64 = cell.rgb.1 as f64 - g as f64;
+            let db: f64 = cell.rgb.2 as f64 - b as f64;
+            return dr * dr + dg * dg + db * db;
+        })
+        .sum();
+}
+
+/// Splits the sub-pixels into the groups a mask selects, sub-pixels past the image edge
+/// are left out
+///
+/// # Arguments
+///
+/// * 'sub_cells'     - Sub-pixels of the character in row-major order, None past the image edge
+/// * 'mask'          - Sub-pixels in the first group
+fn partition(sub_cells: &[Option<Cell>], mask: u32) -> (Vec<&Cell>, Vec<&Cell>) {
+    let (mut fg, mut bg): (Vec<&Cell>, Vec<&Cell>) = (Vec::new(), Vec::new());
+    for (i, sub_cell) in sub_cells.iter().enumerate() {
+        match sub_cell {
+            Some(cell) if mask & (1 << i) != 0 => fg.push(cell),
+            Some(cell) => bg.push(cell),
+            None => (),
+        };
+    }
+    return (fg, bg);
+}
+
+/// Returns the mask splitting the sub-pixels into the foreground and background colors that
+/// fit them best, the brighter of the two colors is drawn in the foreground
+///
+/// # Arguments
+///
+/// * 'sub_cells'     - Sub-pixels of the character in row-major order, None past the image edge
+fn best_split(sub_cells: &[Option<Cell>]) -> u32 {
+    let present: u32 = sub_cells
+        .iter()
+        .enumerate()
+        .filter(|(_, cell)| cell.is_some())
+        .fold(0, |mask, (i, _)| mask | 1 << i);
+    // a flat cell is drawn as a full block, so only strictly better splits replace it
+    let (mut best_mask, mut best_error): (u32, f64) = (present, split_error(&partition(sub_cells, present).0));
+    for mask in 1..present {
+        if mask & !present != 0 {
+            continue;
+        }
+        let (fg, bg) = partition(sub_cells, mask);
+        let error: f64 = split_error(&fg) + split_error(&bg);
+        if error < best_error {
+            (best_mask, best_error) = (mask, error);
+        }
+    }
+
+    let (fg, bg) = partition(sub_cells, best_mask);
+    let brightness = |cells: &[&Cell]| -> f64 {
+        return cells.iter().map(|cell| cell.intensity).sum::<f64>() / cells.len().max(1) as f64;
+    };
+    if !bg.is_empty() && brightness(&bg) > brightness(&fg) {
+        return present & !best_mask;
+    }
+    return best_mask;
+}
+
+/// Splits the sub-pixels of a character into a filled and an empty group and picks the block
+/// character showing them
+///
+/// # Arguments
+///
+/// * 'sub_cells'     - Sub-pixels of the character in row-major order, None past the image edge
+/// * 'filled'        - Sub-pixels picked by thresholding (or dithering), None to pick the best
+///   split into a foreground and a background color instead
+/// * 'mode'          - Block mode
+fn split_cell(sub_cells: &[Option<Cell>], filled: Option<&[bool]>, mode: BlockMode) -> CellGlyph {
+    let mask: u32 = match filled {
+        Some(filled) => filled
+            .iter()
+            .enumerate()
+            .filter(|(_, on)| **on)
+            .fold(0, |mask, (i, _)| mask | 1 << i),
+        None => best_split(sub_cells),
+    };
+    let (fg, bg) = partition(sub_cells, mask);
+    // a flat two tone cell is a full block, its background gets the same color so it isn't
+    // taken for a character that still needs one
+    let bg_rgb: Option<(u8, u8, u8)> = match (filled, bg.is_empty()) {
+        (Some(_), _) => None,
+        (None, false) => Some(mean_rgb(&bg)),
+        (None, true) => Some(mean_rgb(&fg)),
+    };
+    return CellGlyph {
+        token: mode.glyph(mask),
+        rgb: mean_rgb(&fg),
+        bg_rgb,
+    };
+}
+
+/// Converts an image into a row-major grid of block characters, returning the grid and its
+/// width. Every pixel of the image is one sub-pixel of a character.
+///
+/// # Arguments
+///
+/// * 'img'         - Image sampled at the mode's sub-pixel resolution
+/// * 'luminance'   - Model used to compute intensities
+/// * 'mode'        - Block mode
+/// * 'dither'      - Dithering used to pick the filled sub-pixels
+/// * 'color'       - Whether the output is colored, two tone modes then split each character
+///   into the foreground and background colors fitting it best instead of
+///   thresholding its sub-pixels
+/// * 'invert'      - Fill the dark sub-pixels instead of the bright ones, for light backgrounds
+pub fn block_glyphs(
+    img: &RgbaImage,
+    luminance: Luminance,
+    mode: BlockMode,
+    dither: Dither,
+    color: bool,
+    invert: bool,
+) -> (Vec<CellGlyph>, usize) {
+    let (grid_cols, grid_rows) = mode.grid();
+    let (sub_cells, sub_cols) = reduce_cells(img, (1, 1), CellStat::Sample, luminance);
+    let sub_rows: usize = sub_cells.len() / sub_cols.max(1);
+    let cols: usize = sub_cols.div_ceil(grid_cols as usize);
+    let rows: usize = sub_rows.div_ceil(grid_rows as usize);
+    let filled: Option<Vec<bool>> = match color && mode.two_tone() {
+        true => None,
+        false => {
+            let levels: Vec<f64> = sub_cells
+                .iter()
+                .map(|cell| match invert {
+                    true => 1.0 - cell.intensity / MAX_VALUE,
+                    false => cell.intensity / MAX_VALUE,
+                })
+                .collect();
+            let on: Vec<bool> = dither_levels(&levels, sub_cols, 2, dither)
+                .into_iter()
+                .map(|level| level == 1)
+                .collect();
+            Some(on)
+        }
+    };
+
+    let mut glyphs: Vec<CellGlyph> = Vec::with_capacity(cols * rows);
+    for row in 0..rows {
+        for col in 0..cols {
+            let mut cell: Vec<Option<Cell>> = Vec::new();
+            let mut cell_filled: Vec<bool> = Vec::new();
+            for y in 0..grid_rows as usize {
+                for x in 0..grid_cols as usize {
+                    let (sub_x, sub_y) = (col * grid_cols as usize + x, row * grid_rows as usize + y);
+                    if sub_x < sub_cols && sub_y < sub_rows {
+                        let index: usize = sub_y * sub_cols + sub_x;
+                        cell.push(Some(sub_cells[index]));
+                        cell_filled.push(filled.as_ref().is_some_and(|filled| filled[index]));
+                    } else {
+                        cell.push(None);
+                        cell_filled.push(false);
+                    }
+                }
+            }
+            let cell_filled: Option<&[bool]> = filled.as_ref().map(|_| cell_filled.as_slice());
+            glyphs.push(split_cell(&cell, cell_filled, mode));
+        }
+    }
+    return (glyphs, cols);
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn half_blocks_keep_both_rows() {
+        // red over blue in a single column
+        let mut img: RgbaImage = RgbaImage::from_pixel(1, 2, Rgba([255, 0, 0, 255]));
+        img.put_pixel(0, 1, Rgba([0, 0, 255, 255]));
+        let (glyphs, cols) = block_glyphs(&img, Luminance::Rec709, BlockMode::Half, Dither::None, true, false);
+        assert_eq!(1, cols);
+        assert_eq!('\u{2580}', glyphs[0].token);
+        assert_eq!((255, 0, 0), glyphs[0].rgb);
+        assert_eq!(Some((0, 0, 255)), glyphs[0].bg_rgb);
+        assert_eq!((1, 2), block_shape(glyphs[0].token).unwrap().grid);
+        assert_eq!(0b01, block_shape(glyphs[0].token).unwrap().mask);
+    }
+
+    #[test]
+    fn mono_half_blocks() {
+        let mut img: RgbaImage = RgbaImage::from_pixel(2, 2, Rgba([0, 0, 0, 255]));
+        img.put_pixel(0, 1, Rgba([255, 255, 255, 255]));
+        img.put_pixel(1, 0, Rgba([255, 255, 255, 255]));
+        img.put_pixel(1, 1, Rgba([255, 255, 255, 255]));
+        let (glyphs, _) = block_glyphs(&img, Luminance::Rec709, BlockMode::Half, Dither::None, false, false);
+        let tokens: Vec<char> = glyphs.iter().map(|glyph| glyph.token).collect();
+        assert_eq!(vec!['\u{2584}', '\u{2588}'], tokens);
+        assert!(glyphs.iter().all(|glyph| glyph.bg_rgb.is_none()));
+    }
+
+    #[test]
+    fn inverted_blocks_fill_dark_pixels() {
+        let mut img: RgbaImage = RgbaImage::from_pixel(1, 2, Rgba([0, 0, 0, 255]));
+        img.put_pixel(0, 1, Rgba([255, 255, 255, 255]));
+        let (glyphs, _) = block_glyphs(&img, Luminance::Rec709, BlockMode::Half, Dither::None, false, true);
+        assert_eq!('\u{2580}', glyphs[0].token);
+    }
+
+    #[test]
+    fn braille_dots() {
+        // left column of dots on, the rest off
+        assert_eq!('\u{2847}', BlockMode::Braille.glyph(0b01010101));
+        assert_eq!('\u{28FF}', BlockMode::Braille.glyph(0xFF));
+        let shape: BlockShape = block_shape('\u{2847}').unwrap();
+        assert_eq!((2, 4), shape.grid);
+        assert_eq!(0b01010101, shape.mask);
+        assert!(shape.dots);
+
+        let img: RgbaImage = RgbaImage::from_fn(4, 4, |x, _| match x % 2 {
+            0 => Rgba([255, 255, 255, 255]),
+            _ => Rgba([0, 0, 0, 255]),
+        });
+        let (glyphs, cols) =
+            block_glyphs(&img, Luminance::Rec709, BlockMode::Braille, Dither::None, true, false);
+        assert_eq!(2, cols);
+        assert!(glyphs.iter().all(|glyph| glyph.token == '\u{2847}' && glyph.bg_rgb.is_none()));
+    }
+
+    #[test]
+    fn quadrant_and_sextant_glyphs() {
+        assert_eq!('\u{259A}', BlockMode::Quadrant.glyph(0b1001));
+        assert_eq!('\u{1FB00}', BlockMode::Sextant.glyph(0b000001));
+        assert_eq!('\u{1FB14}', BlockMode::Sextant.glyph(0b010110));
+        assert_eq!('\u{1FB3B}', BlockMode::Sextant.glyph(0b111110));
+        assert_eq!('\u{258C}', BlockMode::Sextant.glyph(SEXTANT_LEFT));
+        // every mask draws the same sub-pixels it was made from
+        for mode in [BlockMode::Quadrant, BlockMode::Sextant] {
+            let (grid_cols, grid_rows) = mode.grid();
+            for mask in 1..1 << (grid_cols * grid_rows) {
+                let shape: BlockShape = block_shape(mode.glyph(mask)).unwrap();
+                let mut drawn: u32 = 0;
+                for i in 0..grid_cols * grid_rows {
+                    let x: u32 = (i % grid_cols) * shape.grid.0 / grid_cols;
+                    let y: u32 = (i / grid_cols) * shape.grid.1 / grid_rows;
+                    drawn |= (shape.mask >> (y * shape.grid.0 + x) & 1) << i;
+                }
+                assert_eq!(mask, drawn, "{:?} {:#b}", mode, mask);
+            }
+        }
+    }
+
+    #[test]
+    fn best_two_color_split() {
+        // a red diagonal on green
+        let img: RgbaImage = RgbaImage::from_fn(2, 2, |x, y| match x == y {
+            true => Rgba([255, 0, 0, 255]),
+            false => Rgba([0, 255, 0, 255]),
+        });
+        let (glyphs, _) = block_glyphs(&img, Luminance::Rec709, BlockMode::Quadrant, Dither::None, true, false);
+        // green is the brighter color so it's drawn in the foreground
+        assert_eq!('\u{259E}', glyphs[0].token);
+        assert_eq!((0, 255, 0), glyphs[0].rgb);
+        assert_eq!(Some((255, 0, 0)), glyphs[0].bg_rgb);
+
+        // a dark bottom row with a slightly lighter left pixel stays a single split
+        let img: RgbaImage = RgbaImage::from_fn(2, 3, |x, y| match (x, y) {
+            (0, 2) => Rgba([40, 40, 40, 255]),
+            (_, 2) => Rgba([20, 20, 20, 255]),
+            _ => Rgba([200, 200, 200, 255]),
+        });
+        let (glyphs, _) = block_glyphs(&img, Luminance::Rec709, BlockMode::Sextant, Dither::None, true, false);
+        assert_eq!(BlockMode::Sextant.glyph(0b001111), glyphs[0].token);
+        assert_eq!(Some((30, 30, 30)), glyphs[0].bg_rgb);
+
+        // flat cells are full blocks with a background of the same color
+        let img: RgbaImage = RgbaImage::from_pixel(2, 3, Rgba([90, 10, 10, 255]));
+        let (glyphs, _) = block_glyphs(&img, Luminance::Rec709, BlockMode::Sextant, Dither::None, true, false);
+        assert_eq!('\u{2588}', glyphs[0].token);
+        assert_eq!((90, 10, 10), glyphs[0].rgb);
+        assert_eq!(Some((90, 10, 10)), glyphs[0].bg_rgb);
+    }
+}
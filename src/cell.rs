@@ -0,0 +1,285 @@
+use crate::luminance::Luminance;
+use crate::terminal::is_light;
+use clap::ValueEnum;
+use image::{Rgba, RgbaImage};
+
+/// Statistic used to reduce the pixels covered by a character cell to a single value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum CellStat {
+    /// Top left pixel of the cell only (legacy behavior)
+    Sample,
+    /// Average intensity and color of the cell
+    #[default]
+    Mean,
+    /// Median intensity, colored by the pixel holding it
+    Median,
+    /// Pixel that stands out most from the cell's average, keeps thin lines visible
+    MaxContrast,
+}
+
+/// Which part of a character cell shows the pixel color in color output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ColorTarget {
+    /// Color the character, the background is left alone
+    #[default]
+    Fg,
+    /// Fill the cell with the color and draw the character in black or white on top
+    Bg,
+    /// Fill the cell with the color and draw the character in a lighter or darker shade of it
+    Both,
+}
+
+// how far the character of a `both` cell is moved towards black or white
+const SHADE_AMOUNT: f64 = 0.5;
+
+impl ColorTarget {
+    /// Moves the color of a character onto its background, giving the character a shade
+    /// that stands out from it. Glyphs that already have a background are left as they are.
+    ///
+    /// # Arguments
+    ///
+    /// * 'glyph'       - Character and the color of its cell
+    pub fn apply(&self, glyph: CellGlyph) -> CellGlyph {
+        if *self == ColorTarget::Fg || glyph.bg_rgb.is_some() {
+            return glyph;
+        }
+        let (r, g, b) = glyph.rgb;
+        // dark ink on light cells and light ink on dark ones
+        let ink: f64 = if is_light(glyph.rgb) { 0.0 } else { 255.0 };
+        let amount: f64 = match self {
+            ColorTarget::Both => SHADE_AMOUNT,
+            _ => 1.0,
+        };
+        let shade = |channel: u8| -> u8 {
+            return (channel as f64 + (ink - channel as f64) * amount).round() as u8;
+        };
+        return CellGlyph { token: glyph.token, rgb: (shade(r), shade(g), shade(b)), bg_rgb: Some(glyph.rgb) };
+    }
+}
+
+/// Intensity and color a character cell is rendered with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cell {
+    pub intensity: f64,
+    pub rgb: (u8, u8, u8),
+}
+
+/// Character a cell is drawn with, block characters also color the background
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CellGlyph {
+    pub token: char,
+    pub rgb: (u8, u8, u8),
+    pub bg_rgb: Option<(u8, u8, u8)>,
+}
+
+// cells covered less than this by opaque pixels count as transparent
+const TRANSPARENT_COVERAGE: f64 = 0.5;
+
+/// Returns the intensity of a pixel, its color is expected to be composited over the
+/// background already
+///
+/// # Arguments
+///
+/// * 'pixel'       - Rgba pixel
+/// * 'luminance'   - Model used to compute the intensity
+pub fn pixel_intensity(pixel: &Rgba<u8>, luminance: Luminance) -> f64 {
+    return luminance.intensity(pixel);
+}
+
+/// Blends the color of every pixel over a background color by its alpha. The alpha channel
+/// is kept so transparent cells can still be told apart.
+///
+/// # Arguments
+///
+/// * 'img'         - Image to composite
+/// * 'background'  - Color transparent pixels are shown on
+pub fn composite(img: &mut RgbaImage, background: (u8, u8, u8)) {
+    let background: [f64; 3] = [background.0 as f64, background.1 as f64, background.2 as f64];
+    for pixel in img.pixels_mut() {
+        let alpha: f64 = pixel[3] as f64 / 255.0;
+        for c in 0..3 {
+            pixel[c] = (pixel[c] as f64 * alpha + background[c] * (1.0 - alpha)).round() as u8;
+        }
+    }
+}
+
+/// Returns whether each cell of an image is mostly transparent, in the order of reduce_cells
+///
+/// # Arguments
+///
+/// * 'img'         - Normalized image
+/// * 'cell_size'   - Width and height of a cell in pixels
+pub fn transparent_cells(img: &RgbaImage, cell_size: (u32, u32)) -> Vec<bool> {
+    let cols: u32 = img.width().div_ceil(cell_size.0);
+    let rows: u32 = img.height().div_ceil(cell_size.1);
+    let mut cells: Vec<bool> = Vec::with_capacity((cols * rows) as usize);
+    for row in 0..rows {
+        for col in 0..cols {
+            let (x0, y0) = (col * cell_size.0, row * cell_size.1);
+            let x1: u32 = (x0 + cell_size.0).min(img.width());
+            let y1: u32 = (y0 + cell_size.1).min(img.height());
+            let mut coverage: f64 = 0.0;
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    coverage += img.get_pixel(x, y)[3] as f64 / 255.0;
+                }
+            }
+            let count: f64 = ((x1 - x0) * (y1 - y0)) as f64;
+            cells.push(coverage < TRANSPARENT_COVERAGE * count);
+        }
+    }
+    return cells;
+}
+
+/// Reduces the pixels of one cell to a single intensity and color
+///
+/// # Arguments
+///
+/// * 'img'         - Normalized image
+/// * 'origin'      - Top left pixel of the cell
+/// * 'cell_size'   - Width and height of the cell in pixels, clipped to the image
+/// * 'stat'        - Statistic used to reduce the cell
+/// * 'luminance'   - Model used to compute intensities
+pub fn reduce_cell(
+    img: &RgbaImage,
+    origin: (u32, u32),
+    cell_size: (u32, u32),
+    stat: CellStat,
+    luminance: Luminance,
+) -> Cell {
+    let (x0, y0) = origin;
+    if stat == CellStat::Sample {
+        let pixel: &Rgba<u8> = img.get_pixel(x0, y0);
+        return Cell {
+            intensity: pixel_intensity(pixel, luminance),
+            rgb: (pixel[0], pixel[1], pixel[2]),
+        };
+    }
+
+    let x1: u32 = (x0 + cell_size.0).min(img.width());
+    let y1: u32 = (y0 + cell_size.1).min(img.height());
+    let mut pixels: Vec<(f64, &Rgba<u8>)> = Vec::new();
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let pixel: &Rgba<u8> = img.get_pixel(x, y);
+            pixels.push((pixel_intensity(pixel, luminance), pixel));
+        }
+    }
+    let count: f64 = pixels.len() as f64;
+    let mean: f64 = pixels.iter().map(|(intensity, _)| intensity).sum::<f64>() / count;
+
+    match stat {
+        CellStat::Mean => {
+            let mut sum: [f64; 3] = [0.0; 3];
+            for (_, pixel) in &pixels {
+                for c in 0..3 {
+                    sum[c] += pixel[c] as f64;
+                }
+            }
+            let rgb: [u8; 3] = sum.map(|channel| (channel / count).round() as u8);
+            return Cell {
+                intensity: mean,
+                rgb: (rgb[0], rgb[1], rgb[2]),
+            };
+        }
+        CellStat::Median => {
+            pixels.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let (intensity, pixel) = pixels[pixels.len() / 2];
+            return Cell {
+                intensity,
+                rgb: (pixel[0], pixel[1], pixel[2]),
+            };
+        }
+        _ => {
+            let (intensity, pixel) = pixels
+                .iter()
+                .copied()
+                .max_by(|a, b| (a.0 - mean).abs().total_cmp(&(b.0 - mean).abs()))
+                .unwrap_or((0.0, img.get_pixel(x0, y0)));
+            return Cell {
+                intensity,
+                rgb: (pixel[0], pixel[1], pixel[2]),
+            };
+        }
+    };
+}
+
+/// Reduces an image to a row-major grid of cells, returning the grid and its width
+///
+/// # Arguments
+///
+/// * 'img'         - Normalized image
+/// * 'cell_size'   - Width and height of a cell in pixels
+/// * 'stat'        - Statistic used to reduce each cell
+/// * 'luminance'   - Model used to compute intensities
+pub fn reduce_cells(
+    img: &RgbaImage,
+    cell_size: (u32, u32),
+    stat: CellStat,
+    luminance: Luminance,
+) -> (Vec<Cell>, usize) {
+    let cols: u32 = img.width().div_ceil(cell_size.0);
+    let rows: u32 = img.height().div_ceil(cell_size.1);
+    let mut cells: Vec<Cell> = Vec::with_capacity((cols * rows) as usize);
+    for row in 0..rows {
+        for col in 0..cols {
+            let origin: (u32, u32) = (col * cell_size.0, row * cell_size.1);
+            cells.push(reduce_cell(img, origin, cell_size, stat, luminance));
+        }
+    }
+    return (cells, cols as usize);
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn thin_line_survives() {
+        // a one pixel white line on the odd row of a black cell
+        let mut img: RgbaImage = RgbaImage::from_pixel(1, 2, Rgba([0, 0, 0, 255]));
+        img.put_pixel(0, 1, Rgba([255, 255, 255, 255]));
+        let reduce = |stat: CellStat| reduce_cell(&img, (0, 0), (1, 2), stat, Luminance::Rec709);
+        assert_eq!(0.0, reduce(CellStat::Sample).intensity);
+        assert!((reduce(CellStat::Mean).intensity - 127.5).abs() < 0.01);
+        assert_eq!((128, 128, 128), reduce(CellStat::Mean).rgb);
+        assert!(reduce(CellStat::MaxContrast).intensity > 254.9);
+    }
+
+    #[test]
+    fn grid_covers_partial_cells() {
+        let img: RgbaImage = RgbaImage::from_pixel(5, 5, Rgba([10, 10, 10, 255]));
+        let (cells, width) = reduce_cells(&img, (2, 2), CellStat::Median, Luminance::Rec709);
+        assert_eq!(3, width);
+        assert_eq!(9, cells.len());
+    }
+
+    #[test]
+    fn color_targets() {
+        let glyph = |rgb: (u8, u8, u8)| -> CellGlyph { return CellGlyph { token: '#', rgb, bg_rgb: None } };
+        assert_eq!(glyph((200, 40, 40)), ColorTarget::Fg.apply(glyph((200, 40, 40))));
+        assert_eq!(Some((200, 40, 40)), ColorTarget::Bg.apply(glyph((200, 40, 40))).bg_rgb);
+        assert_eq!((255, 255, 255), ColorTarget::Bg.apply(glyph((200, 40, 40))).rgb);
+        assert_eq!((0, 0, 0), ColorTarget::Bg.apply(glyph((240, 220, 200))).rgb);
+        assert_eq!((120, 110, 100), ColorTarget::Both.apply(glyph((240, 220, 200))).rgb);
+
+        // block characters already color both
+        let block: CellGlyph = CellGlyph { token: '▀', rgb: (1, 2, 3), bg_rgb: Some((4, 5, 6)) };
+        assert_eq!(block, ColorTarget::Bg.apply(block));
+    }
+
+    #[test]
+    fn composite_over_background() {
+        // a half transparent red edge next to a fully transparent pixel
+        let mut img: RgbaImage = RgbaImage::from_fn(2, 2, |x, _| match x {
+            0 => Rgba([255, 0, 0, 128]),
+            _ => Rgba([255, 255, 255, 0]),
+        });
+        composite(&mut img, (0, 0, 255));
+        assert_eq!(Rgba([128, 0, 127, 128]), *img.get_pixel(0, 0));
+        assert_eq!(Rgba([0, 0, 255, 0]), *img.get_pixel(1, 1));
+        assert_eq!(vec![false, true], transparent_cells(&img, (1, 2)));
+        assert_eq!(vec![true], transparent_cells(&img, (2, 2)));
+    }
+}
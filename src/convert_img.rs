@@ -1,30 +1,41 @@
-use crate::utils::{
-    get_file_extension, is_supported_format, AsciiFrame, AsciiToken,
-};
+use crate::adjust::ToneMap;
+use crate::blocks::block_glyphs;
+use crate::cell::{composite, reduce_cells, transparent_cells, CellGlyph};
+use crate::dither::{dither_ramp, level_index, Dither};
+use crate::edges::edge_glyphs;
+use crate::error::AsciifyError;
+use crate::glyph_match::{match_glyphs, GlyphSet, ShapeMetric};
+use crate::img_out::load_font;
+use crate::multi_image::decode_images;
+use crate::renderer::AsciiRenderer;
+use crate::resample::{sharpen, Filter};
+use crate::utils::{detect_format, AsciiFrame, AsciiToken};
 use image::{
-    codecs::gif::GifDecoder, imageops::FilterType, AnimationDecoder, DynamicImage, Frame,
-    GenericImageView, Rgba,
-};
-use std::{
-    fs::{File, OpenOptions},
-    path::PathBuf,
+    codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
+    imageops::{resize, FilterType}, AnimationDecoder, Delay, DynamicImage, Frame,
+    GenericImageView, ImageFormat, RgbaImage,
 };
+use rusttype::Font;
+use std::{fs, io::Cursor};
 
-const ASCII_DETAILED: [char; 70] = [
+pub const ASCII_DETAILED: [char; 70] = [
     ' ', '.', '\'', '`', '^', '"', ',', ':', ';', 'I', 'l', '!', 'i', '>', '<', '~', '+', '_', '-',
     '?', ']', '[', '}', '{', '1', ')', '(', '|', '\\', '/', 't', 'f', 'j', 'r', 'x', 'n', 'u', 'v',
     'c', 'z', 'X', 'Y', 'U', 'J', 'C', 'L', 'Q', '0', 'O', 'Z', 'm', 'w', 'q', 'p', 'd', 'b', 'k',
     'h', 'a', 'o', '*', '#', 'M', 'W', '&', '8', '%', 'B', '@', '$',
 ];
 
-const ASCII_SIMPLE: [char; 10] = [' ', '.', ':', '-', '=', '+', '*', '#', '%', '@'];
+pub const ASCII_SIMPLE: [char; 10] = [' ', '.', ':', '-', '=', '+', '*', '#', '%', '@'];
 
 const MAX_VALUE: f64 = 255.0;
 
+// how long each image of a multi-image container (tiff pages, ico entries) is shown for
+const PAGE_DELAY_MS: u32 = 1000;
+
+#[derive(Debug)]
 pub enum ConvertedFile {
     IMAGE(Vec<AsciiToken>),
     GIF(Vec<AsciiFrame>),
-    ERROR(String),
 }
 
 /// Returns an descaled Dynamic image
@@ -33,57 +44,253 @@ pub enum ConvertedFile {
 ///
 /// * 'img'           - A pixel matrix
 /// * 'scale_factor'  - pixel scale factor used to resize the image
-fn normalize_img(img: DynamicImage, scale_factor: u32) -> DynamicImage {
+pub fn normalize_img(img: DynamicImage, scale_factor: u32) -> DynamicImage {
+    return normalize_img_with(img, scale_factor, FilterType::Gaussian);
+}
+
+/// Returns an descaled Dynamic image, resampled with the given filter
+///
+/// # Arguments
+///
+/// * 'img'           - A pixel matrix
+/// * 'scale_factor'  - pixel scale factor used to resize the image
+/// * 'filter'        - Resampling filter
+pub fn normalize_img_with(img: DynamicImage, scale_factor: u32, filter: FilterType) -> DynamicImage {
     let (width, height) = img.dimensions();
     // if image is smaller than the provided scale we use the original width
     if width < scale_factor {
-        return img.resize(width, height, FilterType::Gaussian);
+        return img.resize(width, height, filter);
     } else {
-        return img.resize(scale_factor, scale_factor, FilterType::Gaussian);
+        return img.resize(scale_factor, scale_factor, filter);
     }
 }
 
+/// Returns the size normalize_img scales an image to, without resizing it
+///
+/// # Arguments
+///
+/// * 'dimensions'    - Width and height of the original image
+/// * 'scale_factor'  - pixel scale factor used to resize the image
+pub fn normalized_size(dimensions: (u32, u32), scale_factor: u32) -> (u32, u32) {
+    let (width, height) = dimensions;
+    if width < scale_factor || width == 0 || height == 0 {
+        return (width, height);
+    }
+    // the same rounding image uses to fit the image inside a scale_factor square
+    let ratio: f64 = (scale_factor as f64 / width as f64).min(scale_factor as f64 / height as f64);
+    let scaled = |side: u32| -> u32 { return ((side as f64 * ratio).round() as u32).max(1) };
+    return (scaled(width), scaled(height));
+}
+
+/// Returns the number of columns and rows of characters an image is shown with when the output
+/// is sized in characters, keeping the image's aspect ratio unless stretched
+///
+/// # Arguments
+///
+/// * 'dimensions'    - Width and height of the original image
+/// * 'width'         - Number of columns, follows from the height when None
+/// * 'height'        - Number of rows, follows from the width when None
+/// * 'stretch'       - Use both the width and height as given instead of fitting inside them
+/// * 'char_aspect'   - Height of a character divided by its width
+pub fn grid_size(
+    dimensions: (u32, u32),
+    width: Option<u32>,
+    height: Option<u32>,
+    stretch: bool,
+    char_aspect: f64,
+) -> (u32, u32) {
+    let (img_width, img_height) = (dimensions.0.max(1) as f64, dimensions.1.max(1) as f64);
+    // the image's height measured in rows when it's one column wide
+    let rows_per_column: f64 = img_height / (img_width * char_aspect);
+    let (columns, rows): (f64, f64) = match (width, height) {
+        (Some(width), Some(height)) if stretch => (width as f64, height as f64),
+        (Some(width), Some(height)) => {
+            let columns: f64 = (width as f64).min(height as f64 / rows_per_column);
+            (columns, (columns * rows_per_column).min(height as f64))
+        }
+        (Some(width), None) => (width as f64, width as f64 * rows_per_column),
+        (None, Some(height)) => (height as f64 / rows_per_column, height as f64),
+        (None, None) => (img_width, img_width * rows_per_column),
+    };
+    return ((columns.round() as u32).max(1), (rows.round() as u32).max(1));
+}
+
+/// Scales an image down by the renderer's scale factor, or to the number of columns and rows
+/// of characters it should be shown with
+///
+/// # Arguments
+///
+/// * 'img'           - A pixel matrix
+/// * 'filter'        - Resampling filter
+/// * 'renderer'      - Conversion settings
+fn scale_img(img: DynamicImage, filter: FilterType, renderer: &AsciiRenderer) -> DynamicImage {
+    let (cell_width, cell_height) = renderer.cell_size;
+    let char_aspect: f64 = match renderer.char_aspect {
+        Some(ratio) => ratio,
+        None if renderer.width.is_none() && renderer.height.is_none() => {
+            return normalize_img_with(img, renderer.scale_factor, filter);
+        }
+        None => cell_height as f64 / cell_width as f64,
+    };
+    let width: Option<u32> = match (renderer.width, renderer.height) {
+        (None, None) => {
+            // keep the number of columns the scale factor would give
+            let (scaled_width, _) = normalized_size(img.dimensions(), renderer.scale_factor);
+            Some(scaled_width.div_ceil(cell_width))
+        }
+        (width, _) => width,
+    };
+    let (columns, rows) = grid_size(img.dimensions(), width, renderer.height, renderer.stretch, char_aspect);
+    return img.resize_exact(columns * cell_width, rows * cell_height, filter);
+}
+
 /// Returns a char mapping for a unsigned int value repesenting a pixel instensity
 ///
 /// # Arguments
 ///
 /// * 'intensity'     - pixel intensity
 /// * 'detail_flag'   - dictate the amount of ascii characters use
-fn asciify_intensity(intensity: u32, ascii_table: &Vec<char>) -> char {
-    let index: f64 = (intensity as f64 / MAX_VALUE) * ((ascii_table.len() - 1) as f64);
-    return ascii_table[index as usize];
+fn asciify_intensity(intensity: u32, ascii_table: &[char]) -> char {
+    let level: f64 = (intensity as f64 / MAX_VALUE) * ((ascii_table.len() - 1) as f64);
+    return ascii_table[level_index(level, ascii_table.len())];
 }
 
-/// Convert a DynamicImage's pixel values into a AsciiTokens
+/// Maps a grid of intensities to characters from the ascii table, dithering across
+/// neighbouring cells if requested
 ///
 /// # Arguments
 ///
-/// * 'img'           - Rgba pixel matrix
+/// * 'intensities'   - Row-major grid of pixel intensities
+/// * 'width'         - Number of cells in a row
 /// * 'ascii_table'   - Char vector of mappable ascii characters
-fn convert_img_to_ascii_tokens(img: DynamicImage, ascii_table: &Vec<char>) -> Vec<AsciiToken> {
+/// * 'dither'        - Dithering mode
+fn quantize_intensities(
+    intensities: &[u32],
+    width: usize,
+    ascii_table: &[char],
+    dither: Dither,
+) -> Vec<char> {
+    if dither == Dither::None {
+        return intensities
+            .iter()
+            .map(|intensity| asciify_intensity(*intensity, ascii_table))
+            .collect();
+    }
+    let max_level: f64 = (ascii_table.len() - 1) as f64;
+    let levels: Vec<f64> = intensities
+        .iter()
+        .map(|intensity| (*intensity as f64 / MAX_VALUE) * max_level)
+        .collect();
+    return dither_ramp(&levels, width, ascii_table.len(), dither)
+        .into_iter()
+        .map(|index| ascii_table[index])
+        .collect();
+}
+
+/// Convert a DynamicImage's pixel values into a AsciiTokens, every pixel of the image
+/// becomes a cell without scaling it first
+///
+/// # Arguments
+///
+/// * 'img'           - Rgba pixel matrix
+/// * 'renderer'      - Conversion settings, e.g. the char mapping and luminance model
+pub fn convert_img_to_ascii_tokens(img: DynamicImage, renderer: &AsciiRenderer) -> Result<Vec<AsciiToken>, AsciifyError> {
+    check_settings(renderer)?;
+    let glyphs: Option<GlyphSet> = load_glyphs(renderer)?;
+    let filter: Filter = renderer.filter.unwrap_or_else(|| Filter::detect(&img));
+    let mut rgba: RgbaImage = img.into_rgba8();
+    composite(&mut rgba, renderer.background);
+    // the image is already the size of the character grid, shapes are matched against it
+    let detail: Option<RgbaImage> = match glyphs.is_some() {
+        true => Some(rgba.clone()),
+        false => None,
+    };
+    let scaled: Vec<ScaledImg> = vec![ScaledImg { img: rgba, detail, sub_filter: sub_filter(filter) }];
+    let tone: Option<ToneMap> = tone_map(&scaled, renderer);
+    let img_tokens: Vec<AsciiToken> = match scaled.into_iter().next() {
+        Some(scaled) => asciify_img(scaled, renderer, glyphs.as_ref(), tone.as_ref()),
+        None => Vec::new(),
+    };
+    if img_tokens.is_empty() {
+        return Err(AsciifyError::EmptyOutput);
+    }
+    return Ok(img_tokens);
+}
+
+/// Convert a DynamicImage's pixel values into a AsciiTokens, sampling glyph shapes and block
+/// sub-pixels from the detailed image when given
+///
+/// # Arguments
+///
+/// * 'img'           - Normalized rgba pixel matrix
+/// * 'detail'        - Image before normalizing
+/// * 'glyphs'        - Glyphs to match against the detailed image when shape matching is enabled
+/// * 'filter'        - Filter used to resample block sub-pixels
+/// * 'renderer'      - Conversion settings, e.g. the char mapping and luminance model
+fn tokenize(
+    img: DynamicImage,
+    detail: Option<&RgbaImage>,
+    glyphs: Option<&GlyphSet>,
+    filter: FilterType,
+    renderer: &AsciiRenderer,
+) -> Vec<AsciiToken> {
     let (width, height) = img.dimensions();
-    let mut img_tokens: Vec<AsciiToken> = Vec::new();
-    for y in 0..height {
-        for x in 0..width {
-            if y % 2 == 0 && x % 1 == 0 {
-                let pixel: Rgba<u8> = img.get_pixel(x, y);
-                let mut intensity: u32 = (pixel[0] / 3 + pixel[1] / 3 + pixel[2] / 3) as u32;
-                if pixel[3] == 0 {
-                    intensity = 0;
-                }
-                let token: char = asciify_intensity(intensity, &ascii_table);
-                img_tokens.push(AsciiToken {
-                    token,
-                    rgb: (pixel[0], pixel[1], pixel[2]),
-                    parent_img_width: width,
-                    parent_img_height: height
-                });
-            }
+    let rgba: RgbaImage = img.to_rgba8();
+    let (glyphs, cols) = match renderer.blocks {
+        Some(mode) => {
+            // resample so every sub-pixel of a character is one pixel, this keeps the number
+            // of characters the same as for ascii output
+            let (grid_cols, grid_rows) = mode.grid();
+            let cols: u32 = width.div_ceil(renderer.cell_size.0);
+            let rows: u32 = height.div_ceil(renderer.cell_size.1);
+            let sampled: RgbaImage = resize(
+                detail.unwrap_or(&rgba),
+                cols * grid_cols,
+                rows * grid_rows,
+                filter,
+            );
+            block_glyphs(&sampled, renderer.luminance, mode, renderer.dither, renderer.color, renderer.invert)
+        }
+        None => {
+            let shapes: Option<(&RgbaImage, &GlyphSet, ShapeMetric)> = match (detail, glyphs, renderer.shape) {
+                (Some(detail), Some(glyphs), Some(metric)) => Some((detail, glyphs, metric)),
+                _ => None,
+            };
+            ascii_glyphs(&rgba, shapes, renderer)
         }
-        if y % 2 == 0 {
+    };
+    let transparent: Vec<bool> = match renderer.transparent {
+        true => transparent_cells(&rgba, renderer.cell_size),
+        false => vec![false; glyphs.len()],
+    };
+
+    let mut img_tokens: Vec<AsciiToken> = Vec::new();
+    for (i, (glyph, transparent)) in glyphs.into_iter().zip(transparent).enumerate() {
+        let glyph: CellGlyph = renderer.color_target.apply(glyph);
+        img_tokens.push(match transparent {
+            true => AsciiToken {
+                token: ' ',
+                rgb: renderer.background,
+                bg_rgb: None,
+                transparent: true,
+                parent_img_width: width,
+                parent_img_height: height
+            },
+            false => AsciiToken {
+                token: glyph.token,
+                rgb: glyph.rgb,
+                bg_rgb: glyph.bg_rgb,
+                transparent: false,
+                parent_img_width: width,
+                parent_img_height: height
+            },
+        });
+        if (i + 1) % cols == 0 {
             img_tokens.push(AsciiToken {
                 token: '\n',
                 rgb: (0, 0, 0),
+                bg_rgb: None,
+                transparent: false,
                 parent_img_width: width,
                 parent_img_height: height
             });
@@ -92,30 +299,210 @@ fn convert_img_to_ascii_tokens(img: DynamicImage, ascii_table: &Vec<char>) -> Ve
     return img_tokens;
 }
 
+/// Picks an ascii character for every cell of the image, returning the grid and its width
+///
+/// # Arguments
+///
+/// * 'rgba'          - Normalized rgba pixel matrix
+/// * 'shapes'        - Image before normalizing, glyphs to match against it and the metric to use
+/// * 'renderer'      - Conversion settings, e.g. the char mapping and luminance model
+fn ascii_glyphs(
+    rgba: &RgbaImage,
+    shapes: Option<(&RgbaImage, &GlyphSet, ShapeMetric)>,
+    renderer: &AsciiRenderer,
+) -> (Vec<CellGlyph>, usize) {
+    let ascii_table: Vec<char> = renderer.ascii_table();
+
+    // each character covers a cell of pixels, cells are taller than they're wide to account
+    // for the shape of terminal characters
+    let (cells, cols) = reduce_cells(
+        rgba,
+        renderer.cell_size,
+        renderer.cell_stat,
+        renderer.luminance,
+    );
+    let intensities: Vec<u32> = cells
+        .iter()
+        .map(|cell| cell.intensity.round().min(MAX_VALUE) as u32)
+        .collect();
+    let mut chars: Vec<char> = match shapes {
+        Some((detail, glyphs, metric)) => {
+            let rows: usize = cells.len() / cols.max(1);
+            match_glyphs(detail, (cols, rows), glyphs, metric, renderer.luminance, renderer.invert)
+        }
+        None => quantize_intensities(&intensities, cols, &ascii_table, renderer.dither),
+    };
+    if let Some(detector) = renderer.edges {
+        let glyphs: Vec<Option<char>> = edge_glyphs(rgba, renderer.cell_size, renderer.luminance, detector);
+        for (token, glyph) in chars.iter_mut().zip(glyphs) {
+            if let Some(glyph) = glyph {
+                *token = glyph;
+            }
+        }
+    }
+    let glyphs: Vec<CellGlyph> = cells
+        .iter()
+        .zip(chars)
+        .map(|(cell, token)| CellGlyph { token, rgb: cell.rgb, bg_rgb: None })
+        .collect();
+    return (glyphs, cols);
+}
+
+/// Rasterizes the glyphs of the character ramp when shape matching is enabled
+///
+/// # Arguments
+///
+/// * 'renderer'      - Conversion settings
+fn load_glyphs(renderer: &AsciiRenderer) -> Result<Option<GlyphSet>, AsciifyError> {
+    if renderer.shape.is_none() {
+        return Ok(None);
+    }
+    let font: Font = load_font(renderer.font.as_deref())?;
+    return Ok(Some(GlyphSet::new(&renderer.ascii_table(), &font)));
+}
+
+/// An image scaled down to the character grid, along with the full resolution image shapes
+/// and block sub-pixels are sampled from
+struct ScaledImg {
+    img: RgbaImage,
+    detail: Option<RgbaImage>,
+    // filter block sub-pixels are resampled with
+    sub_filter: FilterType,
+}
+
+/// Scales an image down to the character grid
+///
+/// # Arguments
+///
+/// * 'img'           - Decoded image
+/// * 'renderer'      - Conversion settings
+/// * 'glyphs'        - Rasterized glyphs when shape matching is enabled
+/// * 'filter'        - Resampling filter, the same for every frame of a file
+fn scale_frame(
+    img: DynamicImage,
+    renderer: &AsciiRenderer,
+    glyphs: Option<&GlyphSet>,
+    filter: Filter,
+) -> ScaledImg {
+    // blend semi-transparent pixels over the background before scaling, so the colors
+    // hidden under transparent pixels don't bleed into the edges
+    let mut rgba: RgbaImage = img.into_rgba8();
+    composite(&mut rgba, renderer.background);
+    let img: DynamicImage = DynamicImage::ImageRgba8(rgba);
+    // shapes and block sub-pixels are sampled from the full resolution image to keep detail
+    // finer than a cell
+    let detail: Option<RgbaImage> = match glyphs.is_some() || renderer.blocks.is_some() {
+        true => Some(img.to_rgba8()),
+        false => None,
+    };
+    let mut img: DynamicImage = scale_img(img, filter.filter_type(), renderer);
+    if let Some(sigma) = renderer.sharpen {
+        img = sharpen(img, sigma);
+    }
+    return ScaledImg { img: img.into_rgba8(), detail, sub_filter: sub_filter(filter) };
+}
+
+/// Returns the filter block sub-pixels are resampled with, they keep the sharper triangle
+/// filter in place of gaussian
+///
+/// # Arguments
+///
+/// * 'filter'        - Filter the image is scaled with
+fn sub_filter(filter: Filter) -> FilterType {
+    match filter {
+        Filter::Gaussian => return FilterType::Triangle,
+        filter => return filter.filter_type(),
+    };
+}
+
+/// Builds the tone curves of the renderer's adjustments from the statistics of every frame,
+/// None when the frames are left unchanged
+///
+/// # Arguments
+///
+/// * 'frames'        - Scaled frames
+/// * 'renderer'      - Conversion settings
+fn tone_map(frames: &[ScaledImg], renderer: &AsciiRenderer) -> Option<ToneMap> {
+    if renderer.adjust.is_identity() {
+        return None;
+    }
+    let imgs: Vec<&RgbaImage> = frames.iter().map(|frame| &frame.img).collect();
+    return Some(ToneMap::new(&imgs, &renderer.adjust, renderer.luminance));
+}
+
+/// Adjusts the tones of a scaled image and converts it into ascii tokens
+///
+/// # Arguments
+///
+/// * 'scaled'        - Scaled image
+/// * 'renderer'      - Conversion settings
+/// * 'glyphs'        - Rasterized glyphs when shape matching is enabled
+/// * 'tone'          - Tone curves shared by every frame
+fn asciify_img(
+    mut scaled: ScaledImg,
+    renderer: &AsciiRenderer,
+    glyphs: Option<&GlyphSet>,
+    tone: Option<&ToneMap>,
+) -> Vec<AsciiToken> {
+    if let Some(tone) = tone {
+        tone.apply(&mut scaled.img);
+        if let Some(detail) = &mut scaled.detail {
+            tone.apply(detail);
+        }
+    }
+    let img: DynamicImage = DynamicImage::ImageRgba8(scaled.img);
+    return tokenize(img, scaled.detail.as_ref(), glyphs, scaled.sub_filter, renderer);
+}
+
 /// Returns a collection of frames, each frame representing an asciified version of the
 /// frame from the original input gif.
 ///
 /// # Arguments
 ///
 /// * 'gif'             - Gif file wrapped in a decoder
-/// * 'ascii_table'     - Char vector of mappable ascii characters
-/// * 'scale_factor'    - Maximum bound used for width
-pub fn convert_gif_to_ascii_tokens(
-    gif: GifDecoder<File>,
-    ascii_table: &Vec<char>,
-    scale_factor: u32,
-) -> Vec<AsciiFrame> {
+/// * 'renderer'        - Conversion settings
+pub fn convert_gif_to_ascii_tokens<'a, D: AnimationDecoder<'a>>(
+    gif: D,
+    renderer: &AsciiRenderer,
+) -> Result<Vec<AsciiFrame>, AsciifyError> {
     let frames: Vec<Frame> = gif
         .into_frames()
         .collect_frames()
-        .expect("Error decoding gif");
-    let mut tokenized_gif: Vec<AsciiFrame> = Vec::new();
+        .map_err(AsciifyError::decode)?;
+    return convert_frames_to_ascii_tokens(frames, renderer);
+}
 
+/// Returns a collection of asciified frames, one for each of the given frames
+///
+/// # Arguments
+///
+/// * 'frames'          - Decoded frames
+/// * 'renderer'        - Conversion settings
+pub fn convert_frames_to_ascii_tokens(
+    frames: Vec<Frame>,
+    renderer: &AsciiRenderer,
+) -> Result<Vec<AsciiFrame>, AsciifyError> {
+    check_settings(renderer)?;
+    let glyphs: Option<GlyphSet> = load_glyphs(renderer)?;
+    // pick the filter once so frames aren't scaled differently from each other
+    let filter: Filter = match (renderer.filter, frames.first()) {
+        (Some(filter), _) => filter,
+        (None, Some(frame)) => Filter::detect(frame.buffer()),
+        (None, None) => Filter::Gaussian,
+    };
+    let mut delays: Vec<(u32, u32)> = Vec::new();
+    let mut scaled: Vec<ScaledImg> = Vec::new();
     for frame in frames {
-        let frame_ratio: (u32, u32) = frame.delay().clone().numer_denom_ms();
-        let mut img: DynamicImage = DynamicImage::ImageRgba8(frame.into_buffer());
-        img = normalize_img(img, scale_factor);
-        let ascii_tokens: Vec<AsciiToken> = convert_img_to_ascii_tokens(img, ascii_table);
+        delays.push(frame.delay().numer_denom_ms());
+        let img: DynamicImage = DynamicImage::ImageRgba8(frame.into_buffer());
+        scaled.push(scale_frame(img, renderer, glyphs.as_ref(), filter));
+    }
+    // every frame is adjusted with the same curves so the animation doesn't flicker
+    let tone: Option<ToneMap> = tone_map(&scaled, renderer);
+    let mut tokenized_gif: Vec<AsciiFrame> = Vec::new();
+
+    for (frame, frame_ratio) in scaled.into_iter().zip(delays) {
+        let ascii_tokens: Vec<AsciiToken> = asciify_img(frame, renderer, glyphs.as_ref(), tone.as_ref());
         let int_delay: (u64, u64) = (frame_ratio.0 as u64, frame_ratio.1 as u64);
         let ascii_frame: AsciiFrame = AsciiFrame {
             frame_tokens: ascii_tokens,
@@ -123,63 +510,155 @@ pub fn convert_gif_to_ascii_tokens(
         };
         tokenized_gif.push(ascii_frame);
     }
-    return tokenized_gif;
+    return Ok(tokenized_gif);
 }
 
-/// Reads file and converts image data into a vector of AsciiToken data.
+/// Checks the renderer's settings can produce any output
 ///
 /// # Arguments
 ///
-/// * 'path_arg'        - File path to the text file
-/// * 'scale_factor'    - Maximum bound used for width
-/// * 'detail_flag'     - Dictate the amount of ascii characters use
-pub fn process_file(
-    path_arg: String,
-    scale_factor: Option<u32>,
-    detail_flag: bool,
-    mapping: Option<String>,
-) -> ConvertedFile {
-    // get char mapping
-    let ascii_table: Vec<char>;
-    if mapping.is_none() {
-        if detail_flag {
-            ascii_table = ASCII_DETAILED.to_vec();
-        } else {
-            ascii_table = ASCII_SIMPLE.to_vec();
-        }
-    } else {
-        ascii_table = mapping.unwrap().chars().collect();
+/// * 'renderer'        - Conversion settings
+fn check_settings(renderer: &AsciiRenderer) -> Result<(), AsciifyError> {
+    if renderer.scale_factor == 0 || renderer.cell_size.0 == 0 || renderer.cell_size.1 == 0 {
+        return Err(AsciifyError::EmptyOutput);
+    }
+    if renderer.width == Some(0) || renderer.height == Some(0) {
+        return Err(AsciifyError::EmptyOutput);
+    }
+    if renderer.char_aspect.is_some_and(|ratio| !ratio.is_finite() || ratio <= 0.0) {
+        return Err(AsciifyError::EmptyOutput);
     }
+    // NaN isn't contained in any range
+    if !(-1.0..=1.0).contains(&renderer.adjust.brightness) {
+        return Err(AsciifyError::InvalidSetting("brightness"));
+    }
+    if !renderer.adjust.contrast.is_finite() || renderer.adjust.contrast < 0.0 {
+        return Err(AsciifyError::InvalidSetting("contrast"));
+    }
+    if !renderer.adjust.gamma.is_finite() || renderer.adjust.gamma <= 0.0 {
+        return Err(AsciifyError::InvalidSetting("gamma"));
+    }
+    if renderer.sharpen.is_some_and(|sigma| !sigma.is_finite() || sigma <= 0.0) {
+        return Err(AsciifyError::InvalidSetting("sharpen"));
+    }
+    if renderer.ascii_table().is_empty() {
+        return Err(AsciifyError::EmptyCharset);
+    }
+    return Ok(());
+}
 
-    // check for scale
-    let scale: u32 = match scale_factor {
-        Some(scale) => scale,
-        None => 72,
+/// Converts a single decoded image into ascii tokens
+///
+/// # Arguments
+///
+/// * 'img'             - Decoded image
+/// * 'renderer'        - Conversion settings
+pub fn convert_image(
+    img: DynamicImage,
+    renderer: &AsciiRenderer,
+) -> Result<Vec<AsciiToken>, AsciifyError> {
+    check_settings(renderer)?;
+    let glyphs: Option<GlyphSet> = load_glyphs(renderer)?;
+    let filter: Filter = renderer.filter.unwrap_or_else(|| Filter::detect(&img));
+    let scaled: Vec<ScaledImg> = vec![scale_frame(img, renderer, glyphs.as_ref(), filter)];
+    let tone: Option<ToneMap> = tone_map(&scaled, renderer);
+    let img_tokens: Vec<AsciiToken> = match scaled.into_iter().next() {
+        Some(scaled) => asciify_img(scaled, renderer, glyphs.as_ref(), tone.as_ref()),
+        None => Vec::new(),
     };
-    if is_supported_format(&path_arg) {
-        let ext: &str = get_file_extension(&path_arg).expect("Could not read file path");
-        if ext == "gif" {
-            let file: File = OpenOptions::new()
-                .read(true)
-                .open(path_arg)
-                .expect("Could not read file");
-            let decoder: GifDecoder<File> =
-                GifDecoder::new(file).expect("Decoder could not decode file");
-            let img_frames: Vec<AsciiFrame> =
-                convert_gif_to_ascii_tokens(decoder, &ascii_table, scale);
-
-            return ConvertedFile::GIF(img_frames);
-        } else {
-            let mut img: DynamicImage =
-                image::open(PathBuf::from(path_arg)).expect("File not Found...");
-            img = normalize_img(img, scale);
-            let img_tokens: Vec<AsciiToken> = convert_img_to_ascii_tokens(img, &ascii_table);
-
-            return ConvertedFile::IMAGE(img_tokens);
+    if img_tokens.is_empty() {
+        return Err(AsciifyError::EmptyOutput);
+    }
+    return Ok(img_tokens);
+}
+
+/// Converts decoded frames into ascii frames
+///
+/// # Arguments
+///
+/// * 'frames'          - Decoded frames
+/// * 'renderer'        - Conversion settings
+fn convert_frames(frames: Vec<Frame>, renderer: &AsciiRenderer) -> Result<ConvertedFile, AsciifyError> {
+    let img_frames: Vec<AsciiFrame> = convert_frames_to_ascii_tokens(frames, renderer)?;
+    if img_frames.is_empty() || img_frames.iter().any(|frame| frame.frame_tokens.is_empty()) {
+        return Err(AsciifyError::EmptyOutput);
+    }
+    return Ok(ConvertedFile::GIF(img_frames));
+}
+
+/// Converts the frames of an animation (gif, animated webp or apng) into ascii frames
+///
+/// # Arguments
+///
+/// * 'gif'             - Animation wrapped in a decoder
+/// * 'renderer'        - Conversion settings
+fn convert_gif<'a, D: AnimationDecoder<'a>>(
+    gif: D,
+    renderer: &AsciiRenderer,
+) -> Result<ConvertedFile, AsciifyError> {
+    let frames: Vec<Frame> = gif
+        .into_frames()
+        .collect_frames()
+        .map_err(AsciifyError::decode)?;
+    return convert_frames(frames, renderer);
+}
+
+/// Converts encoded image data held in memory into a vector of AsciiToken data.
+///
+/// # Arguments
+///
+/// * 'bytes'           - Raw file contents
+/// * 'path_hint'       - Path the data was read from, used when the format can't be detected
+/// * 'renderer'        - Conversion settings
+pub fn convert_bytes(
+    bytes: &[u8],
+    path_hint: Option<&String>,
+    renderer: &AsciiRenderer,
+) -> Result<ConvertedFile, AsciifyError> {
+    let format: ImageFormat = detect_format(bytes, path_hint)?;
+    match format {
+        ImageFormat::Gif => {
+            let decoder: GifDecoder<Cursor<&[u8]>> =
+                GifDecoder::new(Cursor::new(bytes)).map_err(AsciifyError::decode)?;
+            return convert_gif(decoder, renderer);
         }
-    } else {
-        return ConvertedFile::ERROR(String::from("Bad file extension: could not convert file"));
+        ImageFormat::WebP => {
+            let decoder: WebPDecoder<Cursor<&[u8]>> =
+                WebPDecoder::new(Cursor::new(bytes)).map_err(AsciifyError::decode)?;
+            if decoder.has_animation() {
+                return convert_gif(decoder, renderer);
+            }
+        }
+        ImageFormat::Png => {
+            let decoder: PngDecoder<Cursor<&[u8]>> =
+                PngDecoder::new(Cursor::new(bytes)).map_err(AsciifyError::decode)?;
+            if decoder.is_apng() {
+                return convert_gif(decoder.apng(), renderer);
+            }
+        }
+        _ => (),
+    };
+    let mut images: Vec<DynamicImage> = decode_images(bytes, format)?;
+    if images.len() == 1 {
+        return Ok(ConvertedFile::IMAGE(convert_image(images.remove(0), renderer)?));
     }
+    // multi-image containers are shown as frames, one page at a time
+    let frames: Vec<Frame> = images
+        .into_iter()
+        .map(|img| Frame::from_parts(img.into_rgba8(), 0, 0, Delay::from_numer_denom_ms(PAGE_DELAY_MS, 1)))
+        .collect();
+    return convert_frames(frames, renderer);
+}
+
+/// Reads file and converts image data into a vector of AsciiToken data.
+///
+/// # Arguments
+///
+/// * 'path_arg'        - File path to the text file
+/// * 'renderer'        - Conversion settings
+pub fn process_file(path_arg: String, renderer: &AsciiRenderer) -> Result<ConvertedFile, AsciifyError> {
+    let bytes: Vec<u8> = fs::read(&path_arg)?;
+    return convert_bytes(&bytes, Some(&path_arg), renderer);
 }
 
 //-----------
@@ -189,8 +668,23 @@ pub fn process_file(
 mod test {
 
     use super::*;
+    use crate::blocks::BlockMode;
+    use crate::cell::{CellStat, ColorTarget};
+    use crate::luminance::Luminance;
+    use crate::renderer::Charset;
+    use crate::utils::grid_dimensions;
+    use image::Rgba;
+    use std::collections::HashSet;
     use std::path::PathBuf;
 
+    /// Renderer reproducing the output the snapshots below were taken with
+    fn legacy_renderer(charset: Charset) -> AsciiRenderer {
+        return AsciiRenderer::new()
+            .charset(charset)
+            .luminance(Luminance::Average)
+            .cell_stat(CellStat::Sample);
+    }
+
     #[test]
     fn normalize() {
         // resize preserves the aspect ratio
@@ -209,15 +703,15 @@ mod test {
     #[test]
     fn asciify_img() {
         let expected: char = '.';
-        let actual: char = asciify_intensity(38, &ASCII_SIMPLE.to_vec());
+        let actual: char = asciify_intensity(38, &ASCII_SIMPLE);
         assert_eq!(expected, actual);
 
         let expected_2: char = '@';
-        let actual_2: char = asciify_intensity(255, &ASCII_SIMPLE.to_vec());
+        let actual_2: char = asciify_intensity(255, &ASCII_SIMPLE);
         assert_eq!(expected_2, actual_2);
 
         let expected_3: char = '%';
-        let actual_3: char = asciify_intensity(245, &ASCII_DETAILED.to_vec());
+        let actual_3: char = asciify_intensity(245, &ASCII_DETAILED);
         assert_eq!(expected_3, actual_3);
     }
 
@@ -253,7 +747,7 @@ mod test {
         let path: String = String::from("assets/ferris.jpg");
         let mut img: DynamicImage = image::open(PathBuf::from(path)).expect("File not Found...");
         img = normalize_img(img, 72);
-        let res: Vec<AsciiToken> = convert_img_to_ascii_tokens(img, &ASCII_SIMPLE.to_vec());
+        let res: Vec<AsciiToken> = convert_img_to_ascii_tokens(img, &legacy_renderer(Charset::Simple)).unwrap();
         let actual: String = res.iter().map(|ascii_token| ascii_token.token).collect();
         assert_eq!(expected, actual);
     }
@@ -289,14 +783,81 @@ mod test {
 ",
         );
         let path: String = String::from("assets/ferris.jpg");
-        let mapping: Vec<char> = vec!['-', '}'];
+        let mapping: Charset = Charset::Custom(vec!['-', '}']);
         let mut img: DynamicImage = image::open(PathBuf::from(path)).expect("File not Found...");
         img = normalize_img(img, 72);
-        let res: Vec<AsciiToken> = convert_img_to_ascii_tokens(img, &mapping);
+        let res: Vec<AsciiToken> = convert_img_to_ascii_tokens(img, &legacy_renderer(mapping)).unwrap();
         let actual: String = res.iter().map(|ascii_token| ascii_token.token).collect();
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn dither_test() {
+        // dithering a two character mapping mixes both characters into mid tones
+        let path: String = String::from("assets/ferris.jpg");
+        let img: DynamicImage = image::open(PathBuf::from(path)).expect("File not Found...");
+        let img: DynamicImage = normalize_img(img, 72);
+        let renderer: AsciiRenderer = legacy_renderer(Charset::Custom(vec!['-', '}']))
+            .dither(Dither::FloydSteinberg);
+        let res: Vec<AsciiToken> = convert_img_to_ascii_tokens(img, &renderer).unwrap();
+        let actual: String = res.iter().map(|ascii_token| ascii_token.token).collect();
+        let rows: Vec<&str> = actual.lines().collect();
+        assert_eq!(24, rows.len());
+        assert!(rows.iter().all(|row| row.chars().count() == 72));
+        // the body of the crab (legacy output is all '-') now has highlights mixed in
+        assert!(rows[9].contains('}') && rows[9].contains('-'));
+    }
+
+    #[test]
+    fn flat_dither_test() {
+        // a flat image on one of the characters' levels comes out the same dithered or not
+        let charset: Charset = Charset::Custom(vec![' ', '.', '-', '+', '#', '@']);
+        for level in 0..6u8 {
+            let shade: u8 = 51 * level;
+            let img: DynamicImage = DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 16, Rgba([shade, shade, shade, 255])));
+            let tokens = |dither: Dither| -> String {
+                let renderer: AsciiRenderer = legacy_renderer(charset.clone()).dither(dither);
+                return convert_img_to_ascii_tokens(img.clone(), &renderer).unwrap().iter().map(|token| token.token).collect();
+            };
+            let plain: String = tokens(Dither::None);
+            assert!(plain.chars().all(|c| c == '\n' || c == charset.chars()[level as usize]));
+            for dither in [Dither::FloydSteinberg, Dither::Atkinson, Dither::Bayer] {
+                assert_eq!(plain, tokens(dither), "{:?} at level {}", dither, level);
+            }
+        }
+    }
+
+    #[test]
+    fn flat_blocks_bg_target() {
+        // flat cells keep their color instead of turning into white blocks on a red background
+        let img: DynamicImage = DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, Rgba([200, 30, 30, 255])));
+        let renderer: AsciiRenderer = AsciiRenderer::new()
+            .blocks(BlockMode::Half)
+            .color(true)
+            .color_target(ColorTarget::Bg)
+            .width(4);
+        let tokens: Vec<AsciiToken> = convert_image(img, &renderer).unwrap();
+        let cells: Vec<&AsciiToken> = tokens.iter().filter(|token| token.token != '\n').collect();
+        assert!(!cells.is_empty());
+        for cell in cells {
+            assert_eq!('\u{2588}', cell.token);
+            assert_eq!(((200, 30, 30), Some((200, 30, 30))), (cell.rgb, cell.bg_rgb));
+        }
+    }
+
+    #[test]
+    fn cell_size_test() {
+        let path: String = String::from("assets/ferris.jpg");
+        let img: DynamicImage = image::open(PathBuf::from(path)).expect("File not Found...");
+        let img: DynamicImage = normalize_img(img, 72);
+        let renderer: AsciiRenderer = AsciiRenderer::new().cell_size(3, 4);
+        let res: Vec<AsciiToken> = convert_img_to_ascii_tokens(img, &renderer).unwrap();
+        let actual: String = res.iter().map(|ascii_token| ascii_token.token).collect();
+        let rows: Vec<&str> = actual.lines().collect();
+        assert_eq!(12, rows.len());
+        assert!(rows.iter().all(|row| row.chars().count() == 24));
+    }
+
     #[test]
     fn scale_test() {
         let expected: String = String::from(
@@ -312,11 +873,130 @@ mod test {
         let path: String = String::from("assets/ferris.jpg");
         let mut img: DynamicImage = image::open(PathBuf::from(path)).expect("File not Found...");
         img = normalize_img(img, 20);
-        let res: Vec<AsciiToken> = convert_img_to_ascii_tokens(img, &ASCII_SIMPLE.to_vec());
+        let res: Vec<AsciiToken> = convert_img_to_ascii_tokens(img, &legacy_renderer(Charset::Simple)).unwrap();
         let actual: String = res.iter().map(|ascii_token| ascii_token.token).collect();
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn grid_size_test() {
+        // a 2:1 image with characters twice as tall as they're wide
+        assert_eq!((80, 20), grid_size((400, 200), Some(80), None, false, 2.0));
+        assert_eq!((96, 24), grid_size((400, 200), None, Some(24), false, 2.0));
+        assert_eq!((80, 20), grid_size((400, 200), Some(80), Some(24), false, 2.0));
+        assert_eq!((24, 24), grid_size((100, 200), Some(80), Some(24), false, 2.0));
+        assert_eq!((80, 24), grid_size((100, 200), Some(80), Some(24), true, 2.0));
+        // square characters need as many rows as columns for a square image
+        assert_eq!((40, 40), grid_size((200, 200), Some(40), None, false, 1.0));
+    }
+
+    #[test]
+    fn normalized_size_test() {
+        for (width, height, scale) in [(730, 487, 72), (487, 730, 72), (40, 300, 72), (3000, 7, 100), (100, 100, 100)] {
+            let img: DynamicImage = DynamicImage::new_rgba8(width, height);
+            assert_eq!(normalize_img(img, scale).dimensions(), normalized_size((width, height), scale));
+        }
+    }
+
+    #[test]
+    fn exact_size_test() {
+        let bytes: Vec<u8> = fs::read("assets/ferris.jpg").unwrap();
+        let renderer: AsciiRenderer = AsciiRenderer::new().width(80).height(24).stretch(true);
+        let tokens: Vec<AsciiToken> = match convert_bytes(&bytes, None, &renderer).unwrap() {
+            ConvertedFile::IMAGE(tokens) => tokens,
+            _ => panic!("Expected a still image"),
+        };
+        assert_eq!((80, 24), grid_dimensions(&tokens));
+
+        // a wider character aspect needs fewer rows for the same number of columns
+        let renderer: AsciiRenderer = AsciiRenderer::new().width(60).char_aspect(3.0);
+        let tokens: Vec<AsciiToken> = match convert_bytes(&bytes, None, &renderer).unwrap() {
+            ConvertedFile::IMAGE(tokens) => tokens,
+            _ => panic!("Expected a still image"),
+        };
+        let (cols, rows) = grid_dimensions(&tokens);
+        assert_eq!(60, cols);
+        assert_eq!((60.0 * 487.0 / 730.0 / 3.0_f64).round() as usize, rows);
+    }
+
+    #[test]
+    fn transparency_test() {
+        // opaque white on the left, transparent red on the right
+        let img: DynamicImage = DynamicImage::ImageRgba8(RgbaImage::from_fn(40, 40, |x, _| match x < 20 {
+            true => image::Rgba([255, 255, 255, 255]),
+            false => image::Rgba([255, 0, 0, 0]),
+        }));
+        let renderer: AsciiRenderer = AsciiRenderer::new().scale_factor(40).background((0, 0, 255));
+        let tokens: Vec<AsciiToken> = convert_image(img.clone(), &renderer).unwrap();
+        let right: Vec<&AsciiToken> = tokens.iter().filter(|token| token.token != '\n').skip(30).take(5).collect();
+        assert!(right.iter().all(|token| token.rgb == (0, 0, 255) && !token.transparent));
+
+        let tokens: Vec<AsciiToken> = convert_image(img, &renderer.transparent(true)).unwrap();
+        let rows: Vec<&[AsciiToken]> = tokens.split(|token| token.token == '\n').filter(|row| !row.is_empty()).collect();
+        for row in rows {
+            assert!(row[..18].iter().all(|token| !token.transparent && token.token != ' '));
+            assert!(row[22..].iter().all(|token| token.transparent && token.token == ' '));
+        }
+    }
+
+    #[test]
+    fn filter_test() {
+        // nearest keeps the exact colors of the source, gaussian blends neighbouring pixels
+        let img: DynamicImage = image::open("assets/mario.png").unwrap();
+        let colors: HashSet<(u8, u8, u8)> = img.to_rgb8().pixels().map(|pixel| (pixel[0], pixel[1], pixel[2])).collect();
+        let colors_kept = |filter: Filter| -> (usize, usize) {
+            let renderer: AsciiRenderer = legacy_renderer(Charset::Simple).scale_factor(48).filter(filter);
+            let tokens: Vec<AsciiToken> = convert_image(img.clone(), &renderer).unwrap();
+            let cells: Vec<&AsciiToken> = tokens.iter().filter(|token| token.token != '\n').collect();
+            let kept: usize = cells.iter().filter(|token| colors.contains(&token.rgb)).count();
+            return (kept, cells.len());
+        };
+        let (kept, total) = colors_kept(Filter::Nearest);
+        assert_eq!(total, kept);
+        let (kept, total) = colors_kept(Filter::Gaussian);
+        assert!(kept < total * 9 / 10, "{} of {}", kept, total);
+        // mario is a smooth render rather than pixel art
+        assert_eq!(Filter::Gaussian, Filter::detect(&img));
+    }
+
+    #[test]
+    fn filter_detected_once() {
+        // a smooth frame after a pixel art one is scaled with the same nearest filter
+        let sprite: RgbaImage = RgbaImage::from_fn(200, 200, |x, y| match (x / 20 + y / 20) % 2 {
+            0 => Rgba([255, 0, 0, 255]),
+            _ => Rgba([0, 0, 255, 255]),
+        });
+        let img: RgbaImage = image::open("assets/mario.png").unwrap().to_rgba8();
+        let colors: HashSet<(u8, u8, u8)> = img.pixels().map(|pixel| (pixel[0], pixel[1], pixel[2])).collect();
+        let frames: Vec<Frame> = [sprite, img]
+            .into_iter()
+            .map(|canvas| Frame::from_parts(canvas, 0, 0, Delay::from_numer_denom_ms(100, 1)))
+            .collect();
+        let renderer: AsciiRenderer = legacy_renderer(Charset::Simple).scale_factor(48);
+        let frames: Vec<AsciiFrame> = convert_frames_to_ascii_tokens(frames, &renderer).unwrap();
+        assert!(frames[1]
+            .frame_tokens
+            .iter()
+            .filter(|token| token.token != '\n')
+            .all(|token| colors.contains(&token.rgb)));
+    }
+
+    #[test]
+    fn sharpen_test() {
+        let img: DynamicImage = image::open("assets/mario.png").unwrap();
+        let soft: DynamicImage = normalize_img(img.clone(), 48);
+        let sharp: DynamicImage = sharpen(soft.clone(), 1.0);
+        let contrast = |img: &DynamicImage| -> u64 {
+            let grey = img.to_luma8();
+            return grey
+                .enumerate_pixels()
+                .filter(|(x, _, _)| *x > 0)
+                .map(|(x, y, pixel)| (pixel[0] as i64 - grey.get_pixel(x - 1, y)[0] as i64).unsigned_abs())
+                .sum();
+        };
+        assert!(contrast(&sharp) > contrast(&soft));
+    }
+
     // #[test]
     // fn get_dims_test() {let path: String = String::from("assets/ferris.jpg");
     //     let expected: (u32, u32) = (730, 487);
@@ -0,0 +1,183 @@
+use clap::ValueEnum;
+
+// 4x4 bayer threshold matrix, thresholds are (value + 0.5) / 16
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+// error diffusion kernels as (dx, dy, weight)
+const FLOYD_STEINBERG: [(i64, i64, f64); 4] = [
+    (1, 0, 7.0 / 16.0),
+    (-1, 1, 3.0 / 16.0),
+    (0, 1, 5.0 / 16.0),
+    (1, 1, 1.0 / 16.0),
+];
+// atkinson only diffuses 6/8 of the error which keeps highlights and shadows clean
+const ATKINSON: [(i64, i64, f64); 6] = [
+    (1, 0, 1.0 / 8.0),
+    (2, 0, 1.0 / 8.0),
+    (-1, 1, 1.0 / 8.0),
+    (0, 1, 1.0 / 8.0),
+    (1, 1, 1.0 / 8.0),
+    (0, 2, 1.0 / 8.0),
+];
+
+/// Dithering applied when quantizing intensities to characters (and colors to a palette)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Dither {
+    /// Map each cell to its nearest level
+    #[default]
+    None,
+    /// Floyd-Steinberg error diffusion
+    FloydSteinberg,
+    /// Atkinson error diffusion
+    Atkinson,
+    /// Ordered dithering with a 4x4 Bayer matrix
+    Bayer,
+}
+
+/// Quantizes a row-major grid of values, returning the index picked for each cell.
+///
+/// # Arguments
+///
+/// * 'values'    - Grid of values, e.g. a ramp level or an rgb color per cell
+/// * 'width'     - Number of cells in a row
+/// * 'dither'    - Dithering mode
+/// * 'spread'    - Distance between neighbouring quantization levels, scales the bayer offsets
+/// * 'quantize'  - Returns the index of the nearest level and that level's value
+pub fn dither_grid<const N: usize, F>(
+    values: &[[f64; N]],
+    width: usize,
+    dither: Dither,
+    spread: f64,
+    mut quantize: F,
+) -> Vec<usize>
+where
+    F: FnMut(&[f64; N]) -> (usize, [f64; N]),
+{
+    if width == 0 {
+        return Vec::new();
+    }
+    let height: usize = values.len().div_ceil(width);
+    let kernel: &[(i64, i64, f64)] = match dither {
+        Dither::None => {
+            return values.iter().map(|value| quantize(value).0).collect();
+        }
+        Dither::Bayer => {
+            let mut indices: Vec<usize> = Vec::with_capacity(values.len());
+            for (i, value) in values.iter().enumerate() {
+                let threshold: f64 = (BAYER_4X4[(i / width) % 4][(i % width) % 4] as f64 + 0.5) / 16.0;
+                let offset: [f64; N] = value.map(|channel| channel + (threshold - 0.5) * spread);
+                indices.push(quantize(&offset).0);
+            }
+            return indices;
+        }
+        Dither::FloydSteinberg => &FLOYD_STEINBERG,
+        Dither::Atkinson => &ATKINSON,
+    };
+
+    let mut working: Vec<[f64; N]> = values.to_vec();
+    let mut indices: Vec<usize> = Vec::with_capacity(values.len());
+    for i in 0..working.len() {
+        let (x, y) = ((i % width) as i64, (i / width) as i64);
+        let (index, level) = quantize(&working[i]);
+        indices.push(index);
+        let mut error: [f64; N] = [0.0; N];
+        for c in 0..N {
+            error[c] = working[i][c] - level[c];
+        }
+        for (dx, dy, weight) in kernel {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx < 0 || nx >= width as i64 || ny >= height as i64 {
+                continue;
+            }
+            let neighbour: usize = ny as usize * width + nx as usize;
+            if neighbour >= working.len() {
+                continue;
+            }
+            for c in 0..N {
+                working[neighbour][c] += error[c] * weight;
+            }
+        }
+    }
+    return indices;
+}
+
+/// Returns the ramp index a continuous ramp position falls in. Positions are truncated, the
+/// same way undithered characters are picked
+///
+/// # Arguments
+///
+/// * 'level'         - Continuous ramp position
+/// * 'level_count'   - Number of characters in the ramp
+pub fn level_index(level: f64, level_count: usize) -> usize {
+    return level.floor().clamp(0.0, level_count.saturating_sub(1) as f64) as usize;
+}
+
+/// Quantizes a grid of ramp levels (0.0 - levels - 1) to their nearest ramp indices
+///
+/// # Arguments
+///
+/// * 'levels'        - Grid of continuous ramp positions
+/// * 'width'         - Number of cells in a row
+/// * 'level_count'   - Number of characters in the ramp
+/// * 'dither'        - Dithering mode
+pub fn dither_levels(levels: &[f64], width: usize, level_count: usize, dither: Dither) -> Vec<usize> {
+    let max_level: f64 = level_count.saturating_sub(1) as f64;
+    let values: Vec<[f64; 1]> = levels.iter().map(|level| [*level]).collect();
+    return dither_grid(&values, width, dither, 1.0, |value| {
+        let level: f64 = value[0].round().clamp(0.0, max_level);
+        return (level as usize, [level]);
+    });
+}
+
+/// Quantizes a grid of ramp levels (0.0 - levels - 1) to character ramp indices, truncating
+/// like level_index so dithering keeps the characters undithered output picks for flat areas
+///
+/// # Arguments
+///
+/// * 'levels'        - Grid of continuous ramp positions
+/// * 'width'         - Number of cells in a row
+/// * 'level_count'   - Number of characters in the ramp
+/// * 'dither'        - Dithering mode
+pub fn dither_ramp(levels: &[f64], width: usize, level_count: usize, dither: Dither) -> Vec<usize> {
+    // bayer offsets are centered on 0, truncating needs them to run from 0 up to a level
+    let shift: f64 = if dither == Dither::Bayer { 0.5 } else { 0.0 };
+    let values: Vec<[f64; 1]> = levels.iter().map(|level| [*level + shift]).collect();
+    return dither_grid(&values, width, dither, 1.0, |value| {
+        let index: usize = level_index(value[0], level_count);
+        return (index, [index as f64]);
+    });
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn flat_grey_two_levels() {
+        // a flat 50% grey over two levels should come out roughly half on
+        let levels: Vec<f64> = vec![0.5; 64];
+        for dither in [Dither::FloydSteinberg, Dither::Atkinson, Dither::Bayer] {
+            let on: usize = dither_levels(&levels, 8, 2, dither).iter().sum();
+            assert!((24..=40).contains(&on), "{:?} turned on {} cells", dither, on);
+        }
+        let on: usize = dither_levels(&levels, 8, 2, Dither::None).iter().sum();
+        assert!(on == 0 || on == 64);
+    }
+
+    #[test]
+    fn ramp_truncates() {
+        assert_eq!(vec![2, 2, 5], dither_ramp(&[2.0, 2.9, 5.0], 3, 6, Dither::None));
+        // a flat half grey still mixes both characters
+        let on: usize = dither_ramp(&vec![0.5; 64], 8, 2, Dither::Bayer).iter().sum();
+        assert_eq!(32, on);
+    }
+
+    #[test]
+    fn extremes_are_kept() {
+        let levels: Vec<f64> = vec![0.0, 9.0, 0.0, 9.0];
+        for dither in [Dither::FloydSteinberg, Dither::Atkinson, Dither::Bayer] {
+            assert_eq!(vec![0, 9, 0, 9], dither_levels(&levels, 2, 10, dither));
+        }
+    }
+}
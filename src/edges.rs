@@ -0,0 +1,159 @@
+use crate::cell::pixel_intensity;
+use crate::luminance::Luminance;
+use clap::ValueEnum;
+use image::{GrayImage, Luma, RgbaImage};
+use imageproc::{
+    definitions::Image,
+    edges::canny,
+    gradients::{horizontal_sobel, vertical_sobel},
+};
+
+// sobel magnitude a pixel needs to count as an edge, a hard black to white step is ~1020
+const SOBEL_THRESHOLD: f64 = 300.0;
+
+// hysteresis thresholds used by the canny detector
+const CANNY_LOW: f32 = 50.0;
+const CANNY_HIGH: f32 = 120.0;
+
+// share of a cell's pixels that have to lie on an edge for the cell to be drawn as a line
+const EDGE_COVERAGE: f64 = 0.25;
+
+/// Edge detector used to draw outlines with directional characters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EdgeDetector {
+    /// Threshold the Sobel gradient magnitude, picks up soft edges and texture
+    Sobel,
+    /// Canny edge detection, thin connected outlines
+    Canny,
+}
+
+/// Returns the glyph matching the orientation of an edge
+///
+/// # Arguments
+///
+/// * 'angle'     - Direction of the edge in degrees, -90.0 - 90.0 counter clockwise from horizontal
+/// * 'low'       - Whether the edge runs through the bottom half of the cell
+fn edge_glyph(angle: f64, low: bool) -> char {
+    if angle.abs() < 22.5 {
+        if low {
+            return '_';
+        }
+        return '-';
+    }
+    if angle.abs() > 67.5 {
+        return '|';
+    }
+    if angle > 0.0 {
+        return '/';
+    }
+    return '\\';
+}
+
+/// Returns an orientation matching glyph for every cell lying on a strong edge, cells without
+/// an edge are None. Cells are laid out the same way as `cell::reduce_cells`.
+///
+/// # Arguments
+///
+/// * 'img'         - Normalized image
+/// * 'cell_size'   - Width and height of a cell in pixels
+/// * 'luminance'   - Model used to compute intensities
+/// * 'detector'    - Edge detector
+pub fn edge_glyphs(
+    img: &RgbaImage,
+    cell_size: (u32, u32),
+    luminance: Luminance,
+    detector: EdgeDetector,
+) -> Vec<Option<char>> {
+    let (width, height) = img.dimensions();
+    let grey: GrayImage = GrayImage::from_fn(width, height, |x, y| {
+        let intensity: f64 = pixel_intensity(img.get_pixel(x, y), luminance);
+        return Luma([intensity.round().clamp(0.0, 255.0) as u8]);
+    });
+    let gx: Image<Luma<i16>> = horizontal_sobel(&grey);
+    let gy: Image<Luma<i16>> = vertical_sobel(&grey);
+    let edges: GrayImage = match detector {
+        EdgeDetector::Sobel => GrayImage::from_fn(width, height, |x, y| {
+            let (dx, dy) = (gx.get_pixel(x, y)[0] as f64, gy.get_pixel(x, y)[0] as f64);
+            if dx.hypot(dy) >= SOBEL_THRESHOLD {
+                return Luma([255]);
+            }
+            return Luma([0]);
+        }),
+        EdgeDetector::Canny => canny(&grey, CANNY_LOW, CANNY_HIGH),
+    };
+
+    let cols: u32 = width.div_ceil(cell_size.0);
+    let rows: u32 = height.div_ceil(cell_size.1);
+    let mut glyphs: Vec<Option<char>> = Vec::with_capacity((cols * rows) as usize);
+    for row in 0..rows {
+        for col in 0..cols {
+            let (x0, y0) = (col * cell_size.0, row * cell_size.1);
+            let (x1, y1) = ((x0 + cell_size.0).min(width), (y0 + cell_size.1).min(height));
+            // sum the edge directions as doubled angles so opposite gradients don't cancel out
+            let (mut cos2, mut sin2) = (0.0, 0.0);
+            let (mut count, mut low_count) = (0u32, 0u32);
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    if edges.get_pixel(x, y)[0] == 0 {
+                        continue;
+                    }
+                    // the edge runs perpendicular to the gradient, flip y so angles grow upwards
+                    let (ex, ey) = (-(gy.get_pixel(x, y)[0] as f64), -(gx.get_pixel(x, y)[0] as f64));
+                    cos2 += ex * ex - ey * ey;
+                    sin2 += 2.0 * ex * ey;
+                    count += 1;
+                    if (y - y0) * 2 >= y1 - y0 {
+                        low_count += 1;
+                    }
+                }
+            }
+            let area: f64 = ((x1 - x0) * (y1 - y0)) as f64;
+            if count == 0 || (count as f64) < area * EDGE_COVERAGE {
+                glyphs.push(None);
+                continue;
+            }
+            let angle: f64 = sin2.atan2(cos2).to_degrees() / 2.0;
+            glyphs.push(Some(edge_glyph(angle, low_count * 2 > count)));
+        }
+    }
+    return glyphs;
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn glyph_orientation() {
+        assert_eq!('-', edge_glyph(5.0, false));
+        assert_eq!('_', edge_glyph(-5.0, true));
+        assert_eq!('|', edge_glyph(88.0, false));
+        assert_eq!('|', edge_glyph(-80.0, true));
+        assert_eq!('/', edge_glyph(45.0, false));
+        assert_eq!('\\', edge_glyph(-40.0, false));
+    }
+
+    #[test]
+    fn vertical_and_diagonal_edges() {
+        // dark left half, bright right half
+        let img: RgbaImage = RgbaImage::from_fn(8, 8, |x, _| match x < 4 {
+            true => Rgba([0, 0, 0, 255]),
+            false => Rgba([255, 255, 255, 255]),
+        });
+        for detector in [EdgeDetector::Sobel, EdgeDetector::Canny] {
+            let glyphs: Vec<Option<char>> = edge_glyphs(&img, (2, 2), Luminance::Rec709, detector);
+            assert!(glyphs.contains(&Some('|')), "{:?}", detector);
+            assert!(glyphs.iter().all(|glyph| glyph.is_none() || *glyph == Some('|')));
+        }
+
+        // bright below the rising diagonal
+        let img: RgbaImage = RgbaImage::from_fn(8, 8, |x, y| match x + y < 8 {
+            true => Rgba([0, 0, 0, 255]),
+            false => Rgba([255, 255, 255, 255]),
+        });
+        let glyphs: Vec<Option<char>> = edge_glyphs(&img, (2, 2), Luminance::Rec709, EdgeDetector::Sobel);
+        assert!(glyphs.contains(&Some('/')));
+    }
+}
@@ -0,0 +1,76 @@
+use image::ImageError;
+use std::{error::Error, fmt, io};
+
+/// Errors that can occur while converting or outputting an image
+#[derive(Debug)]
+pub enum AsciifyError {
+    /// Reading or writing a file or the terminal failed
+    Io(io::Error),
+    /// The input could not be decoded into an image
+    Decode(ImageError),
+    /// The input is not an image format asciify can convert
+    UnsupportedFormat(String),
+    /// The conversion produced no tokens, e.g. a scale factor of 0
+    EmptyOutput,
+    /// No characters were provided to encode pixel intensities with
+    EmptyCharset,
+    /// The asciified image could not be encoded to the output format
+    Encode(ImageError),
+    /// The font used to draw saved output or match glyph shapes could not be loaded
+    Font(String),
+    /// Playback was stopped with Ctrl-C
+    Interrupted,
+    /// A setting is NaN, infinite or out of range, holds the name of the setting
+    InvalidSetting(&'static str),
+}
+
+impl fmt::Display for AsciifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsciifyError::Io(err) => write!(f, "I/O error: {}", err),
+            AsciifyError::Decode(err) => write!(f, "could not decode image: {}", err),
+            AsciifyError::UnsupportedFormat(msg) => write!(f, "unsupported format: {}", msg),
+            AsciifyError::EmptyOutput => write!(f, "conversion produced no output"),
+            AsciifyError::EmptyCharset => write!(f, "character mapping must not be empty"),
+            AsciifyError::Encode(err) => write!(f, "could not encode output: {}", err),
+            AsciifyError::Font(msg) => write!(f, "could not load font: {}", msg),
+            AsciifyError::Interrupted => write!(f, "interrupted"),
+            AsciifyError::InvalidSetting(name) => write!(f, "invalid value for {}", name),
+        }
+    }
+}
+
+impl Error for AsciifyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AsciifyError::Io(err) => Some(err),
+            AsciifyError::Decode(err) | AsciifyError::Encode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for AsciifyError {
+    fn from(err: io::Error) -> Self {
+        return AsciifyError::Io(err);
+    }
+}
+
+impl AsciifyError {
+    /// Wraps an error raised while decoding, keeping I/O failures separate
+    pub fn decode(err: ImageError) -> Self {
+        match err {
+            ImageError::IoError(err) => return AsciifyError::Io(err),
+            ImageError::Unsupported(_) => return AsciifyError::UnsupportedFormat(err.to_string()),
+            err => return AsciifyError::Decode(err),
+        }
+    }
+
+    /// Wraps an error raised while encoding, keeping I/O failures separate
+    pub fn encode(err: ImageError) -> Self {
+        match err {
+            ImageError::IoError(err) => return AsciifyError::Io(err),
+            err => return AsciifyError::Encode(err),
+        }
+    }
+}
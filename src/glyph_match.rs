@@ -0,0 +1,247 @@
+use crate::cell::pixel_intensity;
+use crate::img_out::{font_scale, SEGMENT_CONSTANT};
+use crate::luminance::Luminance;
+use clap::ValueEnum;
+use image::{
+    imageops::{resize, FilterType},
+    GrayImage, Luma, Rgba, RgbaImage,
+};
+use imageproc::drawing::draw_text_mut;
+use rusttype::Font;
+
+// resolution glyphs and image cells are compared at, every sample covers 2x2 pixels of a
+// rendered glyph
+pub const SHAPE_GRID: (u32, u32) = (SEGMENT_CONSTANT / 2, SEGMENT_CONSTANT);
+
+// ssim stabilizing constants for values in the range 0.0 - 1.0
+const SSIM_C1: f64 = 0.01 * 0.01;
+const SSIM_C3: f64 = 0.03 * 0.03 / 2.0;
+
+// cells flatter than this have no structure to compare, they're matched on brightness instead
+const FLAT_VARIANCE: f64 = 0.005;
+
+/// Distance used to compare the shape of an image cell with a glyph
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ShapeMetric {
+    /// Sum of squared differences, favours matching the brightness of a cell over its shape
+    L2,
+    /// Structural similarity, favours glyphs with the same pattern of light and dark
+    Ssim,
+}
+
+/// Glyphs of a character ramp rasterized at the shape grid resolution
+#[derive(Debug, Clone)]
+pub struct GlyphSet {
+    glyphs: Vec<(char, Vec<f64>)>,
+    // ink coverage of the densest glyph, image cells are scaled to it
+    ink: f64,
+}
+
+/// A character drawn on a canvas, before it's cropped and sampled at the shape grid
+struct RenderedGlyph {
+    c: char,
+    canvas: GrayImage,
+    // leftmost and rightmost inked columns, None when nothing was drawn
+    columns: Option<(u32, u32)>,
+}
+
+/// Returns the structural similarity of two equally sized patterns, the contrast term is
+/// left out so faint and strong strokes of the same shape score alike
+///
+/// # Arguments
+///
+/// * 'a'         - First pattern
+/// * 'b'         - Second pattern
+fn ssim(a: &[f64], b: &[f64]) -> f64 {
+    let n: f64 = a.len() as f64;
+    let mean_a: f64 = a.iter().sum::<f64>() / n;
+    let mean_b: f64 = b.iter().sum::<f64>() / n;
+    let (mut var_a, mut var_b, mut covar) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        var_a += (x - mean_a) * (x - mean_a);
+        var_b += (y - mean_b) * (y - mean_b);
+        covar += (x - mean_a) * (y - mean_b);
+    }
+    let (var_a, var_b, covar) = (var_a / n, var_b / n, covar / n);
+    let luminance: f64 = (2.0 * mean_a * mean_b + SSIM_C1) / (mean_a * mean_a + mean_b * mean_b + SSIM_C1);
+    let structure: f64 = (covar + SSIM_C3) / ((var_a * var_b).sqrt() + SSIM_C3);
+    return luminance * structure;
+}
+
+impl GlyphSet {
+    /// Rasterizes every character with the same font setup saved output is drawn with. Like
+    /// in a terminal each glyph is centered horizontally and the baseline is shared, the area
+    /// all glyphs are drawn into is then stretched over the cell.
+    ///
+    /// # Arguments
+    ///
+    /// * 'chars'     - Candidate characters
+    /// * 'font'      - Font the characters are drawn with
+    pub fn new(chars: &[char], font: &Font) -> GlyphSet {
+        // draw on a wide canvas so glyphs wider than a character segment aren't clipped
+        let (width, height) = (SEGMENT_CONSTANT * 2, SEGMENT_CONSTANT * 2);
+        let mut rendered: Vec<RenderedGlyph> = Vec::new();
+        let (mut top, mut bottom, mut ink_width) = (height, 0, 0);
+        for c in chars {
+            let mut canvas: GrayImage = GrayImage::new(width, height);
+            draw_text_mut(&mut canvas, Luma([255]), 0, 0, font_scale(), font, &c.to_string());
+            let inked: Vec<(u32, u32)> = canvas
+                .enumerate_pixels()
+                .filter(|(_, _, pixel)| pixel[0] > 0)
+                .map(|(x, y, _)| (x, y))
+                .collect();
+            let columns: Option<(u32, u32)> = match inked.is_empty() {
+                true => None,
+                false => {
+                    let left: u32 = inked.iter().map(|(x, _)| *x).min().unwrap_or(0);
+                    let right: u32 = inked.iter().map(|(x, _)| *x).max().unwrap_or(0);
+                    top = top.min(inked.iter().map(|(_, y)| *y).min().unwrap_or(top));
+                    bottom = bottom.max(inked.iter().map(|(_, y)| *y).max().unwrap_or(bottom));
+                    ink_width = ink_width.max(right - left + 1);
+                    Some((left, right))
+                }
+            };
+            rendered.push(RenderedGlyph { c: *c, canvas, columns });
+        }
+        if top > bottom {
+            // nothing was drawn, e.g. a charset of spaces
+            (top, bottom, ink_width) = (0, height - 1, width);
+        }
+
+        let mut glyphs: Vec<(char, Vec<f64>)> = Vec::new();
+        for RenderedGlyph { c, canvas, columns } in rendered {
+            let mut cell: GrayImage = GrayImage::new(ink_width, bottom - top + 1);
+            if let Some((left, right)) = columns {
+                let offset: u32 = (ink_width - (right - left + 1)) / 2;
+                for y in top..=bottom {
+                    for x in left..=right {
+                        cell.put_pixel(x - left + offset, y - top, *canvas.get_pixel(x, y));
+                    }
+                }
+            }
+            let sampled: GrayImage = resize(&cell, SHAPE_GRID.0, SHAPE_GRID.1, FilterType::Triangle);
+            let coverage: Vec<f64> = sampled.pixels().map(|pixel| pixel[0] as f64 / 255.0).collect();
+            glyphs.push((c, coverage));
+        }
+        let ink: f64 = glyphs
+            .iter()
+            .map(|(_, coverage)| coverage.iter().sum::<f64>() / coverage.len() as f64)
+            .fold(0.0, f64::max);
+        return GlyphSet { glyphs, ink };
+    }
+
+    /// Returns the glyph whose shape best fits a cell pattern
+    ///
+    /// # Arguments
+    ///
+    /// * 'pattern'   - Cell intensities in the range 0.0 - 1.0 sampled on the shape grid
+    /// * 'metric'    - Distance used to compare shapes
+    pub fn best_match(&self, pattern: &[f64], metric: ShapeMetric) -> char {
+        // bright cells map to dense glyphs, so scale the cell to the ink the font can produce
+        let scaled: Vec<f64> = pattern.iter().map(|value| value * self.ink).collect();
+        let mean: f64 = pattern.iter().sum::<f64>() / pattern.len() as f64;
+        let variance: f64 =
+            pattern.iter().map(|value| (value - mean) * (value - mean)).sum::<f64>() / pattern.len() as f64;
+        let metric: ShapeMetric = match variance < FLAT_VARIANCE {
+            true => ShapeMetric::L2,
+            false => metric,
+        };
+        let mut best: (char, f64) = (' ', f64::MAX);
+        for (c, coverage) in &self.glyphs {
+            let distance: f64 = match metric {
+                ShapeMetric::L2 => scaled.iter().zip(coverage).map(|(x, y)| (x - y) * (x - y)).sum(),
+                ShapeMetric::Ssim => -ssim(&scaled, coverage),
+            };
+            if distance < best.1 {
+                best = (*c, distance);
+            }
+        }
+        return best.0;
+    }
+}
+
+/// Matches every cell of a cols x rows grid laid over the image to a glyph, sampling the
+/// image at the shape grid resolution so detail finer than a cell is kept
+///
+/// # Arguments
+///
+/// * 'img'         - Image before it's scaled down to the character grid
+/// * 'grid'        - Number of columns and rows of characters
+/// * 'glyphs'      - Candidate glyphs
+/// * 'metric'      - Distance used to compare shapes
+/// * 'luminance'   - Model used to compute intensities
+/// * 'invert'      - Match glyphs against the dark parts of the image, for light backgrounds
+pub fn match_glyphs(
+    img: &RgbaImage,
+    grid: (usize, usize),
+    glyphs: &GlyphSet,
+    metric: ShapeMetric,
+    luminance: Luminance,
+    invert: bool,
+) -> Vec<char> {
+    let (cols, rows) = (grid.0 as u32, grid.1 as u32);
+    let sampled: RgbaImage =
+        resize(img, cols * SHAPE_GRID.0, rows * SHAPE_GRID.1, FilterType::Triangle);
+    let mut chars: Vec<char> = Vec::with_capacity((cols * rows) as usize);
+    for row in 0..rows {
+        for col in 0..cols {
+            let mut pattern: Vec<f64> = Vec::with_capacity((SHAPE_GRID.0 * SHAPE_GRID.1) as usize);
+            for y in 0..SHAPE_GRID.1 {
+                for x in 0..SHAPE_GRID.0 {
+                    let pixel: &Rgba<u8> =
+                        sampled.get_pixel(col * SHAPE_GRID.0 + x, row * SHAPE_GRID.1 + y);
+                    let intensity: f64 = pixel_intensity(pixel, luminance) / 255.0;
+                    pattern.push(match invert {
+                        true => 1.0 - intensity,
+                        false => intensity,
+                    });
+                }
+            }
+            chars.push(glyphs.best_match(&pattern, metric));
+        }
+    }
+    return chars;
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::img_out::load_font;
+
+    #[test]
+    fn glyphs_match_themselves() {
+        let font: Font = load_font(None).unwrap();
+        let set: GlyphSet = GlyphSet::new(&[' ', '|', '-', '@'], &font);
+        for (c, coverage) in &set.glyphs {
+            let pattern: Vec<f64> = coverage.iter().map(|value| value / set.ink).collect();
+            assert_eq!(*c, set.best_match(&pattern, ShapeMetric::L2));
+        }
+    }
+
+    #[test]
+    fn vertical_bar_matches_pipe() {
+        let font: Font = load_font(None).unwrap();
+        let set: GlyphSet = GlyphSet::new(&[' ', '|', '-', '_'], &font);
+        // a thin bright column in the middle of a dark cell
+        let img: RgbaImage = RgbaImage::from_fn(12, 24, |x, _| match x == 5 || x == 6 {
+            true => Rgba([255, 255, 255, 255]),
+            false => Rgba([0, 0, 0, 255]),
+        });
+        assert_eq!(vec!['|'], match_glyphs(&img, (1, 1), &set, ShapeMetric::Ssim, Luminance::Rec709, false));
+    }
+
+    #[test]
+    fn flat_cells_match_brightness() {
+        let font: Font = load_font(None).unwrap();
+        let set: GlyphSet = GlyphSet::new(&[' ', '.', '@'], &font);
+        let img: RgbaImage = RgbaImage::from_fn(36, 24, |x, _| match x / 12 {
+            0 => Rgba([0, 0, 0, 255]),
+            _ => Rgba([255, 255, 255, 255]),
+        });
+        for metric in [ShapeMetric::L2, ShapeMetric::Ssim] {
+            assert_eq!(vec![' ', '@', '@'], match_glyphs(&img, (3, 1), &set, metric, Luminance::Rec709, false));
+            assert_eq!(vec!['@', ' ', ' '], match_glyphs(&img, (3, 1), &set, metric, Luminance::Rec709, true));
+        }
+    }
+}
@@ -1,39 +1,186 @@
-use crate::convert_img::{process_file, ConvertedFile};
-use crate::utils::{build_output_file_name, supports_truecolor, AsciiFrame, AsciiToken};
-use image::{ImageBuffer, Rgba, RgbaImage, Frame, Delay, codecs::gif::{GifEncoder, Repeat}};
-use imageproc::drawing::draw_text_mut;
-use rgb2ansi256::rgb_to_ansi256;
+use crate::convert_img::ConvertedFile;
+use crate::dither::Dither;
+use crate::error::AsciifyError;
+use crate::blocks::{block_shape, BlockShape};
+use crate::palette::{ansi16_code, Palette};
+use crate::renderer::{AsciiRenderer, OutputTarget};
+use crate::terminal::{color_depth, is_light, ColorDepth, Playback};
+use crate::utils::{grid_dimensions, AsciiFrame, AsciiToken};
+use image::{
+    DynamicImage, ImageBuffer, ImageError, ImageFormat, Rgba, RgbaImage, Frame, Delay,
+    codecs::gif::{GifEncoder, Repeat},
+    error::{EncodingError, ImageFormatHint},
+};
+use imageproc::{
+    drawing::{draw_filled_circle_mut, draw_filled_rect_mut, draw_text_mut},
+    rect::Rect,
+};
 use rusttype::{Font, Scale};
-use std::{fs::{OpenOptions, File}, io::Write, process, thread, time::Duration};
-use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+use std::{
+    fs::{self, OpenOptions, File},
+    io::{self, BufWriter, Cursor, IsTerminal, Stdout, StdoutLock, Write},
+    path::Path,
+    time::Duration,
+};
 
-const SEGMENT_CONSTANT: u32 = 12;
+// width in pixels of a character drawn on saved output, rows are drawn twice as tall
+pub const SEGMENT_CONSTANT: u32 = 12;
 
-/// Parses ascii pixel vector and prints colored output to the terminal
+/// Color a character or the background of its cell is printed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TermColor {
+    // whatever color the terminal uses by default
+    Default,
+    Ansi16(u8),
+    Ansi256(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl TermColor {
+    /// Returns the parameters of the escape sequence selecting the color
+    ///
+    /// # Arguments
+    ///
+    /// * 'background'    - Whether to set the background color
+    fn sgr(&self, background: bool) -> String {
+        let layer: u8 = if background { 48 } else { 38 };
+        match self {
+            TermColor::Default => return (layer + 1).to_string(),
+            TermColor::Ansi16(index) => return ansi16_code(*index, background).to_string(),
+            TermColor::Ansi256(index) => return format!("{};5;{}", layer, index),
+            TermColor::Rgb(r, g, b) => return format!("{};2;{};{};{}", layer, r, g, b),
+        };
+    }
+}
+
+/// Writes colored characters to the terminal, sending an escape sequence only when the colors
+/// change. Colors are reset when it's dropped, so an error halfway through an image doesn't
+/// leave the shell tinted.
+struct ColorWriter<W: Write> {
+    out: W,
+    fg: TermColor,
+    bg: TermColor,
+}
+
+impl<W: Write> ColorWriter<W> {
+    fn new(out: W) -> Self {
+        return ColorWriter { out, fg: TermColor::Default, bg: TermColor::Default };
+    }
+
+    /// Writes a character in the given colors
+    ///
+    /// # Arguments
+    ///
+    /// * 'token'     - Character to write
+    /// * 'fg'        - Color of the character
+    /// * 'bg'        - Color of the cell's background
+    fn write_char(&mut self, token: char, fg: TermColor, bg: TermColor) -> io::Result<()> {
+        // a space doesn't show its foreground color, keep the current one
+        let fg: TermColor = if token == ' ' { self.fg } else { fg };
+        let mut params: Vec<String> = Vec::new();
+        if fg != self.fg {
+            params.push(fg.sgr(false));
+        }
+        if bg != self.bg {
+            params.push(bg.sgr(true));
+        }
+        if !params.is_empty() {
+            write!(self.out, "\x1b[{}m", params.join(";"))?;
+        }
+        self.fg = fg;
+        self.bg = bg;
+        return write!(self.out, "{}", token);
+    }
+
+    /// Ends a row, dropping the background color first so it doesn't fill the rest of the line
+    fn newline(&mut self) -> io::Result<()> {
+        if self.bg != TermColor::Default {
+            write!(self.out, "\x1b[49m")?;
+            self.bg = TermColor::Default;
+        }
+        return writeln!(self.out);
+    }
+
+    /// Resets the terminal's colors and flushes the buffered output
+    fn reset(&mut self) -> io::Result<()> {
+        if self.fg != TermColor::Default || self.bg != TermColor::Default {
+            write!(self.out, "\x1b[0m")?;
+            self.fg = TermColor::Default;
+            self.bg = TermColor::Default;
+        }
+        return self.out.flush();
+    }
+}
+
+impl<W: Write> Drop for ColorWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.reset();
+    }
+}
+
+/// Parses ascii pixel vector and writes colored output for the terminal
 ///
 /// # Arguments
 ///
+/// * 'out'       - Where the output is written, e.g. stdout
 /// * 'tokens'    - Vector of Ascii tokens representing each pixel from the original image
-fn write_color_output(tokens: Vec<AsciiToken>) {
-    let mut stdout = StandardStream::stdout(ColorChoice::Always);
-    let truecolor_flag = supports_truecolor();
-    for token in tokens {
-        if truecolor_flag {
-            stdout
-                .set_color(ColorSpec::new().set_fg(Some(Color::Rgb(
-                    token.rgb.0,
-                    token.rgb.1,
-                    token.rgb.2,
-                ))))
-                .expect("Failed to set color");
-        } else {
-            let ansci_val: u8 = rgb_to_ansi256(token.rgb.0, token.rgb.1, token.rgb.2);
-            stdout
-                .set_color(ColorSpec::new().set_fg(Some(Color::Ansi256(ansci_val))))
-                .expect("Failed to set color");
+/// * 'dither'    - Dithering applied when colors are mapped to a palette
+/// * 'depth'     - Number of colors the terminal is sent
+/// * 'palette'   - Palette colors are mapped to, None for truecolor
+fn write_color_output<W: Write>(
+    out: W,
+    tokens: Vec<AsciiToken>,
+    dither: Dither,
+    depth: ColorDepth,
+    palette: Option<&mut Palette>,
+) -> Result<(), AsciifyError> {
+    // background colors are only matched when some cell is filled
+    let filled: bool = tokens.iter().any(|token| token.bg_rgb.is_some());
+    let (ansi_indices, ansi_bg_indices): (Vec<u8>, Vec<u8>) = match palette {
+        Some(palette) if filled => (palette.indices(&tokens, dither), palette.bg_indices(&tokens, dither)),
+        Some(palette) => (palette.indices(&tokens, dither), Vec::new()),
+        None => (Vec::new(), Vec::new()),
+    };
+    let mut writer: ColorWriter<W> = ColorWriter::new(out);
+    for (i, token) in tokens.into_iter().enumerate() {
+        if token.token == '\n' {
+            writer.newline()?;
+            continue;
         }
-        write!(&mut stdout, "{}", token.token).expect("failed to write");
+        let (fg, bg): (TermColor, Option<TermColor>) = match depth {
+            ColorDepth::Truecolor => (
+                TermColor::Rgb(token.rgb.0, token.rgb.1, token.rgb.2),
+                token.bg_rgb.map(|(r, g, b)| TermColor::Rgb(r, g, b)),
+            ),
+            ColorDepth::Ansi256 => (
+                TermColor::Ansi256(ansi_indices[i]),
+                token.bg_rgb.map(|_| TermColor::Ansi256(ansi_bg_indices[i])),
+            ),
+            ColorDepth::Ansi16 => (
+                TermColor::Ansi16(ansi_indices[i]),
+                token.bg_rgb.map(|_| TermColor::Ansi16(ansi_bg_indices[i])),
+            ),
+        };
+        // transparent cells show the terminal's own colors
+        match token.transparent {
+            true => writer.write_char(token.token, TermColor::Default, TermColor::Default)?,
+            false => writer.write_char(token.token, fg, bg.unwrap_or(TermColor::Default))?,
+        };
     }
+    writer.reset()?;
+    return Ok(());
+}
+
+/// Returns the number of colors console output is printed with, None for plain output
+///
+/// # Arguments
+///
+/// * 'renderer'      - Output settings
+fn console_colors(renderer: &AsciiRenderer) -> Option<ColorDepth> {
+    if !renderer.color {
+        return None;
+    }
+    return color_depth(renderer.color_mode);
 }
 
 /// Prints asciified image to the console
@@ -41,17 +188,33 @@ fn write_color_output(tokens: Vec<AsciiToken>) {
 /// # Arguments
 ///
 /// * 'img_tokens'    - Vector of Ascii tokens representing each pixel from the original image
-/// * 'color_flag'    - Defines color output for the terminal
-pub fn print_img_to_console(img_tokens: Vec<AsciiToken>, color_flag: bool) {
-    if color_flag {
-        write_color_output(img_tokens)
+/// * 'renderer'      - Output settings, e.g. color output for the terminal
+pub fn print_img_to_console(img_tokens: Vec<AsciiToken>, renderer: &AsciiRenderer) -> Result<(), AsciifyError> {
+    if let Some(depth) = console_colors(renderer) {
+        let mut palette: Option<Palette> = Palette::for_depth(depth);
+        let stdout: BufWriter<StdoutLock> = BufWriter::new(io::stdout().lock());
+        write_color_output(stdout, img_tokens, renderer.palette_dither(), depth, palette.as_mut())?;
     } else {
-        let img_str: String = img_tokens
-            .iter()
-            .map(|ascii_token| ascii_token.token)
-            .collect();
-        println!("{}", img_str);
+        write_plain_output(io::stdout().lock(), &img_tokens)?;
     }
+    return Ok(());
+}
+
+/// Writes ascii tokens without color. Every row already ends in a newline token, so nothing
+/// is added after the last one.
+///
+/// # Arguments
+///
+/// * 'out'           - Where the output is written, e.g. stdout
+/// * 'img_tokens'    - Vector of Ascii tokens representing each pixel from the original image
+fn write_plain_output<W: Write>(mut out: W, img_tokens: &[AsciiToken]) -> Result<(), AsciifyError> {
+    let img_str: String = img_tokens
+        .iter()
+        .map(|ascii_token| ascii_token.token)
+        .collect();
+    write!(out, "{}", img_str)?;
+    out.flush()?;
+    return Ok(());
 }
 
 /// Prints gif frames to the console
@@ -59,57 +222,138 @@ pub fn print_img_to_console(img_tokens: Vec<AsciiToken>, color_flag: bool) {
 /// # Arguments
 ///
 /// * 'img_frames'    - Vector of asciified gif frames
-/// * 'color_flag'    - Determines color output
-pub fn print_gif_to_console(img_frames: Vec<AsciiFrame>, color_flag: bool) {
-    if color_flag {
+/// * 'renderer'      - Output settings, e.g. color output for the terminal
+pub fn print_gif_to_console(img_frames: Vec<AsciiFrame>, renderer: &AsciiRenderer) -> Result<(), AsciifyError> {
+    let depth: Option<ColorDepth> = console_colors(renderer);
+    // one palette for every frame so its cache of matched colors is shared
+    let mut palette: Option<Palette> = depth.and_then(Palette::for_depth);
+    if !io::stdout().is_terminal() {
+        // piped output gets every frame one after the other, without the playback escapes
+        let mut stdout: BufWriter<StdoutLock> = BufWriter::new(io::stdout().lock());
         for frame in img_frames {
-            process::Command::new("clear").status().unwrap();
-            write_color_output(frame.frame_tokens);
-            let delay: u64 = frame.delay.0 / frame.delay.1;
-            thread::sleep(Duration::from_millis(delay));
+            write_frame(&mut stdout, frame.frame_tokens, renderer, depth, palette.as_mut())?;
         }
-    } else {
-        for frame in img_frames {
-            let img_str: String = frame
-                .frame_tokens
-                .iter()
-                .map(|ascii_token| ascii_token.token)
-                .collect();
-            process::Command::new("clear").status().unwrap();
-            println!("{}", img_str);
-            let delay: u64 = frame.delay.0 / frame.delay.1;
-            thread::sleep(Duration::from_millis(delay));
+        stdout.flush()?;
+        return Ok(());
+    }
+    // frames are drawn over each other on the alternate screen, the terminal is restored
+    // when playback goes out of scope, also on errors and Ctrl-C
+    let mut playback: Playback<BufWriter<Stdout>> = Playback::start(BufWriter::new(io::stdout()))?;
+    for frame in img_frames {
+        playback.frame(|out| -> Result<(), AsciifyError> {
+            return write_frame(out, frame.frame_tokens, renderer, depth, palette.as_mut());
+        })?;
+        let delay: u64 = frame.delay.0 / frame.delay.1;
+        if !playback.wait(Duration::from_millis(delay)) {
+            return Err(AsciifyError::Interrupted);
         }
     }
+    return Ok(());
 }
 
-/// Wrapper function to that processes img files and outputs them to the terminal
+/// Writes the tokens of a single gif frame, in color when a color depth is given
 ///
 /// # Arguments
 ///
-/// * 'path_arg'            - File path to the text file
-/// * 'scale_factor'        - Maximum bound used for width
-/// * 'detail_flag'         - Dictate the amount of ascii characters use
-pub fn output_to_console(
-    path_arg: String,
-    scale_factor: Option<u32>,
-    detail_flag: bool,
-    color_flag: bool,
-    mapping: Option<String>,
-) -> Result<(), String> {
-    match process_file(path_arg, scale_factor, detail_flag, mapping) {
-        ConvertedFile::IMAGE(img_tokens) => {
-            print_img_to_console(img_tokens, color_flag);
-            return Ok(());
-        }
-        ConvertedFile::GIF(img_frames) => {
-            print_gif_to_console(img_frames, color_flag);
-            return Ok(());
-        }
-        ConvertedFile::ERROR(msg) => return Err(msg)
+/// * 'out'           - Where the frame is written, e.g. stdout
+/// * 'frame_tokens'  - Ascii tokens of the frame
+/// * 'renderer'      - Output settings
+/// * 'depth'         - Number of colors the terminal shows, None for plain output
+/// * 'palette'       - Terminal palette colors are matched against, shared between frames
+fn write_frame<W: Write>(
+    out: W,
+    frame_tokens: Vec<AsciiToken>,
+    renderer: &AsciiRenderer,
+    depth: Option<ColorDepth>,
+    palette: Option<&mut Palette>,
+) -> Result<(), AsciifyError> {
+    match depth {
+        Some(depth) => return write_color_output(out, frame_tokens, renderer.palette_dither(), depth, palette),
+        None => return write_plain_output(out, &frame_tokens),
     };
 }
 
+/// Loads the font used to draw saved output, falling back to the font bundled with asciify
+///
+/// # Arguments
+///
+/// * 'font_path'       - Path to a TrueType or OpenType font file
+pub fn load_font(font_path: Option<&Path>) -> Result<Font<'static>, AsciifyError> {
+    let (font, name): (Vec<u8>, String) = match font_path {
+        Some(path) => (fs::read(path)?, path.display().to_string()),
+        None => (
+            Vec::from(include_bytes!("../assets/Roboto-Regular.ttf") as &[u8]),
+            String::from("Roboto-Regular.ttf"),
+        ),
+    };
+    return Font::try_from_vec(font)
+        .ok_or_else(|| AsciifyError::Font(format!("{} is not a valid font", name)));
+}
+
+/// Returns the scale characters are drawn at on saved output
+pub fn font_scale() -> Scale {
+    let font_size: f32 = SEGMENT_CONSTANT as f32 * 1.5;
+    return Scale {
+        x: font_size,
+        y: font_size,
+    };
+}
+
+/// Returns the size of the area characters are drawn on, excluding padding
+///
+/// # Arguments
+///
+/// * 'tokens'          - Ascii tokens with rows separated by newline tokens
+fn canvas_dimensions(tokens: &[AsciiToken]) -> (u32, u32) {
+    let (cols, rows) = grid_dimensions(tokens);
+    // each row is drawn two segments tall to keep the character aspect
+    return (cols as u32 * SEGMENT_CONSTANT, rows as u32 * SEGMENT_CONSTANT * 2);
+}
+
+/// Draws a block character as filled sub-rectangles of its character cell
+///
+/// # Arguments
+///
+/// * 'img_canvas'      - Image buffer we write our data to
+/// * 'origin'          - Top left corner of the character cell
+/// * 'shape'           - Sub-pixel grid and filled sub-pixels of the block character
+/// * 'fg'              - Color of the filled sub-pixels
+/// * 'bg'              - Color of the empty sub-pixels, None leaves them untouched
+fn draw_block(
+    img_canvas: &mut RgbaImage,
+    origin: (i32, i32),
+    shape: BlockShape,
+    fg: Rgba<u8>,
+    bg: Option<Rgba<u8>>,
+) {
+    let (width, height) = (SEGMENT_CONSTANT, SEGMENT_CONSTANT * 2);
+    // the background fills the whole cell like in a terminal, also around braille dots
+    if let Some(bg) = bg {
+        draw_filled_rect_mut(img_canvas, Rect::at(origin.0, origin.1).of_size(width, height), bg);
+    }
+    let (grid, mask) = (shape.grid, shape.mask);
+    for row in 0..grid.1 {
+        for col in 0..grid.0 {
+            if mask & (1 << (row * grid.0 + col)) == 0 {
+                continue;
+            }
+            // split the cell evenly, the last sub-pixel takes up any remainder
+            let (x0, y0) = (width * col / grid.0, height * row / grid.1);
+            let (x1, y1) = (width * (col + 1) / grid.0, height * (row + 1) / grid.1);
+            if shape.dots {
+                // braille dots are drawn as circles in the middle of their area
+                let center: (i32, i32) =
+                    (origin.0 + ((x0 + x1) / 2) as i32, origin.1 + ((y0 + y1) / 2) as i32);
+                let radius: i32 = ((x1 - x0).min(y1 - y0) / 3) as i32;
+                draw_filled_circle_mut(img_canvas, center, radius, fg);
+            } else {
+                let rect: Rect = Rect::at(origin.0 + x0 as i32, origin.1 + y0 as i32).of_size(x1 - x0, y1 - y0);
+                draw_filled_rect_mut(img_canvas, rect, fg);
+            }
+        }
+    }
+}
+
 /// Write ascii tokens to an image buffer
 /// 
 /// # Arguments
@@ -117,12 +361,14 @@ pub fn output_to_console(
 /// * 'img_canvas'      - Image buffer we write our data to
 /// * 'img_frames'      - Vector of asciified gif frames
 /// * 'color_flag'      - Defines color output for the terminal
+/// * 'ink'             - Color characters are drawn in without color output
 /// * 'scale'           - scaling variable for a single character
 /// * 'font'            - font for the output character
 pub fn write_img(
     img_canvas: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
     img_tokens: Vec<AsciiToken>,
     color_flag: bool,
+    ink: Rgba<u8>,
     scale: Scale,
     font: &Font
 ) {
@@ -137,12 +383,31 @@ pub fn write_img(
         }
 
         x_pointer = x_pointer + SEGMENT_CONSTANT as i32;
+        if token.transparent {
+            continue;
+        }
 
         let rgb_val: Rgba<u8>;
         if color_flag {
             rgb_val = Rgba([token.rgb.0, token.rgb.1, token.rgb.2, 255]);
         } else {
-            rgb_val = Rgba([255, 255, 255, 255]);
+            rgb_val = ink;
+        }
+
+        // block characters are drawn as rectangles, fonts rarely have glyphs for them
+        if let Some(shape) = block_shape(token.token) {
+            let bg_val: Option<Rgba<u8>> = match (color_flag, token.bg_rgb) {
+                (true, Some((r, g, b))) => Some(Rgba([r, g, b, 255])),
+                _ => None,
+            };
+            draw_block(img_canvas, (x_pointer, y_pointer), shape, rgb_val, bg_val);
+            continue;
+        }
+
+        // characters with a background color fill their whole cell first
+        if let (true, Some((r, g, b))) = (color_flag, token.bg_rgb) {
+            let cell: Rect = Rect::at(x_pointer, y_pointer).of_size(SEGMENT_CONSTANT, SEGMENT_CONSTANT * 2);
+            draw_filled_rect_mut(img_canvas, cell, Rgba([r, g, b, 255]));
         }
 
         draw_text_mut(
@@ -157,116 +422,473 @@ pub fn write_img(
     }
 }
 
-/// Write asciified image to png or jpg file for output
+/// Returns the background color of saved output and the color characters are drawn in on it
+/// without color output
 ///
-/// # Aurguments
+/// # Arguments
 ///
-/// * 'img'                 - Vector of Ascii tokens representing each pixel from the original image
-/// * 'color_flag'          - Defines color output for the terminal
-/// * 'output_file_name     - File name of the output file
-pub fn save_img(tokens: Vec<AsciiToken>, color_flag: bool, output_file_name: String) {
-    let (w, h) = (tokens[0].parent_img_width, tokens[0].parent_img_height);
-    let y_axis: u32 = h * SEGMENT_CONSTANT;
-    let x_axis: u32 = w * SEGMENT_CONSTANT;
+/// * 'renderer'            - Output settings
+fn canvas_colors(renderer: &AsciiRenderer) -> (Rgba<u8>, Rgba<u8>) {
+    let (r, g, b) = renderer.canvas;
+    let ink: Rgba<u8> = match is_light(renderer.canvas) {
+        true => Rgba([0, 0, 0, 255]),
+        false => Rgba([255, 255, 255, 255]),
+    };
+    return (Rgba([r, g, b, 255]), ink);
+}
+
+/// Draw asciified image onto a canvas
+///
+/// # Arguments
+///
+/// * 'tokens'              - Vector of Ascii tokens representing each pixel from the original image
+/// * 'renderer'            - Output settings, e.g. color and font
+fn draw_img(tokens: Vec<AsciiToken>, renderer: &AsciiRenderer) -> Result<RgbaImage, AsciifyError> {
+    if tokens.is_empty() {
+        return Err(AsciifyError::EmptyOutput);
+    }
+    let (x_axis, y_axis) = canvas_dimensions(&tokens);
+    let (background, ink) = canvas_colors(renderer);
     let mut img_canvas = RgbaImage::from_pixel(
         x_axis + (SEGMENT_CONSTANT as u32 * 2), 
         y_axis + (SEGMENT_CONSTANT as u32 * 2), 
-        Rgba([0, 0, 0, 255]));
+        background);
 
-    let font: Vec<u8> = Vec::from(include_bytes!("../assets/Roboto-Regular.ttf") as &[u8]);
-    let font: Font = Font::try_from_vec(font).unwrap();
+    let font: Font = load_font(renderer.font.as_deref())?;
+    write_img(&mut img_canvas, tokens, renderer.color, ink, font_scale(), &font);
+    return Ok(img_canvas);
+}
 
-    let font_size: f32 = SEGMENT_CONSTANT as f32 * 1.5;
-    let scale = Scale {
-        x: font_size,
-        y: font_size,
-    };
-    write_img(&mut img_canvas, tokens, color_flag, scale, &font);
-    img_canvas.save(output_file_name).unwrap();
+/// Encode asciified image in the given format and write it out
+///
+/// # Arguments
+///
+/// * 'tokens'              - Vector of Ascii tokens representing each pixel from the original image
+/// * 'renderer'            - Output settings, e.g. color and font
+/// * 'format'              - Image format of the output
+/// * 'writer'              - Destination of the encoded image, e.g. a file or stdout
+pub fn encode_img<W: Write>(
+    tokens: Vec<AsciiToken>,
+    renderer: &AsciiRenderer,
+    format: ImageFormat,
+    writer: &mut W,
+) -> Result<(), AsciifyError> {
+    let img_canvas: RgbaImage = draw_img(tokens, renderer)?;
+    // some encoders need to seek, so encode into memory first
+    let mut encoded: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    DynamicImage::ImageRgba8(img_canvas)
+        .write_to(&mut encoded, format)
+        .map_err(AsciifyError::encode)?;
+    writer.write_all(encoded.get_ref())?;
+    writer.flush()?;
+    return Ok(());
 }
 
-/// Convert asciified images frames into a gif
+/// Write asciified image to png or jpg file for output, the format is picked from the
+/// file extension
+///
+/// # Aurguments
+///
+/// * 'img'                 - Vector of Ascii tokens representing each pixel from the original image
+/// * 'renderer'            - Output settings, e.g. color and font
+/// * 'output_file_name     - File name of the output file
+pub fn save_img(tokens: Vec<AsciiToken>, renderer: &AsciiRenderer, output_file_name: &Path) -> Result<(), AsciifyError> {
+    let format: ImageFormat = ImageFormat::from_path(output_file_name)
+        .map_err(|err| AsciifyError::UnsupportedFormat(err.to_string()))?;
+    let mut file: File = create_output_file(output_file_name)?;
+    return encode_img(tokens, renderer, format, &mut file);
+}
+
+/// Draw asciified frames onto canvases of the same size, keeping each frame's delay. Frames
+/// smaller than the largest one are drawn in the top left corner.
 /// 
 /// # Arguments
 /// 
 /// * 'frames'              - A collection of encoded ascii frames.
-/// * 'color_flag'          - Defines color output for the terminal
-/// * 'output_file_name     - File name of the output file
-pub fn save_gif(frames: Vec<AsciiFrame>, color_flag: bool, output_file_name: &String) {
-    let (w, h) = (
-        frames[0].frame_tokens[0].parent_img_width,
-        frames[0].frame_tokens[0].parent_img_height,
-    );
-    let y_axis: u32 = h * SEGMENT_CONSTANT;
-    let x_axis: u32 = w * SEGMENT_CONSTANT;
-
-    let font: Vec<u8> = Vec::from(include_bytes!("../assets/Roboto-Regular.ttf") as &[u8]);
-    let font: Font = Font::try_from_vec(font).unwrap();
+/// * 'renderer'            - Output settings, e.g. color and font
+fn draw_frames(frames: Vec<AsciiFrame>, renderer: &AsciiRenderer) -> Result<Vec<Frame>, AsciifyError> {
+    if frames.is_empty() || frames[0].frame_tokens.is_empty() {
+        return Err(AsciifyError::EmptyOutput);
+    }
+    // pages of ico and tiff files can differ in size, every canvas fits the largest one
+    let (x_axis, y_axis) = frames
+        .iter()
+        .map(|frame| canvas_dimensions(&frame.frame_tokens))
+        .fold((0, 0), |(x_max, y_max), (x, y)| (x_max.max(x), y_max.max(y)));
 
-    let font_size: f32 = SEGMENT_CONSTANT as f32 * 1.5;
-    let scale = Scale {
-        x: font_size,
-        y: font_size,
-    };
-    println!("Saving gif...");
+    let font: Font = load_font(renderer.font.as_deref())?;
+    let scale: Scale = font_scale();
+    let (background, ink) = canvas_colors(renderer);
     let mut result: Vec<Frame> = Vec::new();
     // TODO: optimize this?
     for frame in frames {
-        // give each frame a blackground, add segment * 2 for extra padding on each axis
+        // give each frame a background, add segment * 2 for extra padding on each axis
         let mut img_canvas = RgbaImage::from_pixel(
             x_axis + (SEGMENT_CONSTANT as u32 * 2), 
             y_axis + (SEGMENT_CONSTANT as u32 * 2), 
-            Rgba([0, 0, 0, 255]));
+            background);
         
-        write_img(&mut img_canvas, frame.frame_tokens, color_flag, scale, &font);
+        write_img(&mut img_canvas, frame.frame_tokens, renderer.color, ink, scale, &font);
         let f: Frame = Frame::from_parts(img_canvas, 0, 0, Delay::from_numer_denom_ms(
             frame.delay.0 as u32, 
             frame.delay.1 as u32
         ));
         result.push(f);
     }
+    return Ok(result);
+}
 
+/// Opens (or truncates) the output file
+/// 
+/// # Arguments
+/// 
+/// * 'output_file_name     - File name of the output file
+fn create_output_file(output_file_name: &Path) -> Result<File, AsciifyError> {
     let file: File = OpenOptions::new()
         .write(true)
         .create(true)
         .truncate(true)
-        .open(output_file_name)
-        .expect("File could not be read");
+        .open(output_file_name)?;
+    return Ok(file);
+}
+
+/// Encode asciified images frames as a gif and write it out
+/// 
+/// # Arguments
+/// 
+/// * 'frames'              - A collection of encoded ascii frames.
+/// * 'renderer'            - Output settings, e.g. color and font
+/// * 'writer'              - Destination of the encoded gif, e.g. a file or stdout
+pub fn encode_gif<W: Write>(frames: Vec<AsciiFrame>, renderer: &AsciiRenderer, writer: &mut W) -> Result<(), AsciifyError> {
+    let result: Vec<Frame> = draw_frames(frames, renderer)?;
+
+    let mut encoder: GifEncoder<&mut W> = GifEncoder::new(writer);
+    encoder.set_repeat(Repeat::Infinite).map_err(AsciifyError::encode)?;
+    encoder.encode_frames(result).map_err(AsciifyError::encode)?;
+    return Ok(());
+}
+
+/// Convert asciified images frames into a gif
+/// 
+/// # Arguments
+/// 
+/// * 'frames'              - A collection of encoded ascii frames.
+/// * 'renderer'            - Output settings, e.g. color and font
+/// * 'output_file_name     - File name of the output file
+pub fn save_gif(frames: Vec<AsciiFrame>, renderer: &AsciiRenderer, output_file_name: &Path) -> Result<(), AsciifyError> {
+    println!("Saving gif...");
+    let mut file: BufWriter<File> = BufWriter::new(create_output_file(output_file_name)?);
+    encode_gif(frames, renderer, &mut file)?;
+    file.flush()?;
+    return Ok(());
+}
 
-    let mut encoder: GifEncoder<File> = GifEncoder::new(file);
-    encoder.set_repeat(Repeat::Infinite).unwrap();
-    encoder.encode_frames(result).unwrap();
+/// Wraps an error from the png encoder, keeping I/O failures separate
+fn png_error(err: png::EncodingError) -> AsciifyError {
+    match err {
+        png::EncodingError::IoError(err) => return AsciifyError::Io(err),
+        err => {
+            return AsciifyError::encode(ImageError::Encoding(EncodingError::new(
+                ImageFormatHint::Exact(ImageFormat::Png),
+                err,
+            )));
+        }
+    };
 }
 
-/// Save asciified img to file
+/// Encode asciified images frames as an animated png and write it out, unlike gifs apngs
+/// aren't limited to a 256 color palette
 ///
 /// # Arguments
 ///
-/// * 'path_arg'        - File path to the text file
-/// * 'detail_flag'     - Dictate the amount of ascii characters use
-/// * 'color_flag'      - Defines color output for the terminal
-/// * 'scale_factor'     - Maximum bound used for width
-pub fn save(
-    path_arg: String,
-    detail_flag: bool,
-    color_flag: bool,
-    mapping: Option<String>,
-    scale_factor: Option<u32>,
-) -> Result<(), String>{
-    let file_name: String = build_output_file_name(&path_arg).unwrap();
-
-    let ascii_data: ConvertedFile = process_file(path_arg, scale_factor, detail_flag, mapping);
-    match ascii_data {
-        ConvertedFile::IMAGE(img) => {
-            save_img(img, color_flag, file_name);
-            return Ok(());
-        },
-        ConvertedFile::GIF(gif) => {
-            save_gif(gif, color_flag, &file_name);
+/// * 'frames'              - A collection of encoded ascii frames.
+/// * 'renderer'            - Output settings, e.g. color and font
+/// * 'writer'              - Destination of the encoded apng, e.g. a file or stdout
+pub fn encode_apng<W: Write>(frames: Vec<AsciiFrame>, renderer: &AsciiRenderer, writer: &mut W) -> Result<(), AsciifyError> {
+    let result: Vec<Frame> = draw_frames(frames, renderer)?;
+    let (width, height) = result[0].buffer().dimensions();
+
+    let mut encoder: png::Encoder<&mut W> = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    // 0 plays loops forever
+    encoder.set_animated(result.len() as u32, 0).map_err(png_error)?;
+    let mut png_writer: png::Writer<&mut W> = encoder.write_header().map_err(png_error)?;
+    for frame in result {
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let delay_ms: u32 = (numer / denom.max(1)).min(u16::MAX as u32);
+        png_writer.set_frame_delay(delay_ms as u16, 1000).map_err(png_error)?;
+        png_writer.write_image_data(frame.buffer().as_raw()).map_err(png_error)?;
+    }
+    png_writer.finish().map_err(png_error)?;
+    return Ok(());
+}
+
+/// Convert asciified images frames into an animated png
+///
+/// # Arguments
+///
+/// * 'frames'              - A collection of encoded ascii frames.
+/// * 'renderer'            - Output settings, e.g. color and font
+/// * 'output_file_name     - File name of the output file
+pub fn save_apng(frames: Vec<AsciiFrame>, renderer: &AsciiRenderer, output_file_name: &Path) -> Result<(), AsciifyError> {
+    println!("Saving apng...");
+    let mut file: BufWriter<File> = BufWriter::new(create_output_file(output_file_name)?);
+    encode_apng(frames, renderer, &mut file)?;
+    file.flush()?;
+    return Ok(());
+}
+
+/// Write converted data to the output target configured on the renderer
+///
+/// # Arguments
+///
+/// * 'converted'       - Asciified image or gif
+/// * 'renderer'        - Output settings
+pub fn write_output(converted: ConvertedFile, renderer: &AsciiRenderer) -> Result<(), AsciifyError> {
+    match (converted, &renderer.target) {
+        (ConvertedFile::IMAGE(img_tokens), OutputTarget::Console) => {
+            return print_img_to_console(img_tokens, renderer);
+        }
+        (ConvertedFile::GIF(img_frames), OutputTarget::Console) => {
+            return print_gif_to_console(img_frames, renderer);
+        }
+        (ConvertedFile::IMAGE(img_tokens), OutputTarget::File(path)) => {
+            return save_img(img_tokens, renderer, path);
+        }
+        (ConvertedFile::GIF(img_frames), OutputTarget::File(path)) => {
+            // frames saved under a png name are written as an animated png
+            if ImageFormat::from_path(path).ok() == Some(ImageFormat::Png) {
+                return save_apng(img_frames, renderer, path);
+            }
+            return save_gif(img_frames, renderer, path);
+        }
+        (ConvertedFile::IMAGE(img_tokens), OutputTarget::Stdout(format)) => {
+            return encode_img(img_tokens, renderer, *format, &mut io::stdout().lock());
+        }
+        (ConvertedFile::GIF(img_frames), OutputTarget::Stdout(format)) => {
+            let mut stdout: BufWriter<StdoutLock> = BufWriter::new(io::stdout().lock());
+            if *format == ImageFormat::Png {
+                encode_apng(img_frames, renderer, &mut stdout)?;
+            } else {
+                encode_gif(img_frames, renderer, &mut stdout)?;
+            }
+            stdout.flush()?;
             return Ok(());
-        },
-        ConvertedFile::ERROR(msg) => {
-            return Err(msg);
         }
+    };
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::convert_img::{convert_frames_to_ascii_tokens, process_file};
+    use crate::terminal::{fit_area, Fit};
+    use std::path::PathBuf;
+
+    #[test]
+    fn exact_console_size() {
+        let renderer: AsciiRenderer = AsciiRenderer::new().width(80).height(24).stretch(true);
+        let tokens: Vec<AsciiToken> = match process_file(String::from("assets/ferris.jpg"), &renderer).unwrap() {
+            ConvertedFile::IMAGE(tokens) => tokens,
+            _ => panic!("Expected a still image"),
+        };
+        let mut out: Vec<u8> = Vec::new();
+        write_plain_output(&mut out, &tokens).unwrap();
+        let printed: String = String::from_utf8(out).unwrap();
+        // no blank line after the last row
+        assert!(printed.ends_with('\n') && !printed.ends_with("\n\n"));
+        let rows: Vec<&str> = printed.lines().collect();
+        assert_eq!(24, rows.len());
+        assert!(rows.iter().all(|row| row.chars().count() == 80));
+    }
+
+    #[test]
+    fn fits_terminal() {
+        // the rows printed plus the prompt after them stay inside the terminal
+        let (columns, rows) = fit_area((100, 30));
+        for fit in [Fit::Width, Fit::Height, Fit::Both] {
+            let renderer: AsciiRenderer = AsciiRenderer::new().fit(fit, columns, rows);
+            let tokens: Vec<AsciiToken> = match process_file(String::from("assets/ferris.jpg"), &renderer).unwrap() {
+                ConvertedFile::IMAGE(tokens) => tokens,
+                _ => panic!("Expected a still image"),
+            };
+            let mut out: Vec<u8> = Vec::new();
+            write_plain_output(&mut out, &tokens).unwrap();
+            let printed: String = String::from_utf8(out).unwrap();
+            let printed_rows: Vec<&str> = printed.lines().collect();
+            if fit != Fit::Height {
+                assert!(printed_rows.iter().all(|row| row.chars().count() <= 100));
+            }
+            if fit != Fit::Width {
+                assert!(printed_rows.len() < 30);
+            }
+        }
+    }
+
+    #[test]
+    fn frames_of_different_sizes() {
+        let red: (u8, u8, u8) = (200, 30, 30);
+        let frame = |cols: usize, rows: usize| -> AsciiFrame {
+            let mut tokens: Vec<AsciiToken> = Vec::new();
+            for _ in 0..rows {
+                for _ in 0..cols {
+                    tokens.push(AsciiToken {
+                        token: '\u{2588}', rgb: red, bg_rgb: None, transparent: false,
+                        parent_img_width: cols as u32, parent_img_height: rows as u32,
+                    });
+                }
+                tokens.push(AsciiToken {
+                    token: '\n', rgb: red, bg_rgb: None, transparent: false,
+                    parent_img_width: cols as u32, parent_img_height: rows as u32,
+                });
+            }
+            return AsciiFrame { frame_tokens: tokens, delay: (100, 1) };
+        };
+        let renderer: AsciiRenderer = AsciiRenderer::new().color(true).canvas((0, 0, 0));
+        let canvases: Vec<Frame> = draw_frames(vec![frame(2, 1), frame(4, 3)], &renderer).unwrap();
+        // characters are drawn one segment in from the edges
+        let (width, height) = (SEGMENT_CONSTANT * 6, SEGMENT_CONSTANT * 8);
+        for canvas in &canvases {
+            assert_eq!((width, height), canvas.buffer().dimensions());
+        }
+        // the bottom right cell of the larger frame isn't cut off
+        let (x, y) = (width - SEGMENT_CONSTANT - 1, height - SEGMENT_CONSTANT - 1);
+        assert_eq!(&Rgba([200, 30, 30, 255]), canvases[1].buffer().get_pixel(x, y));
+        assert_eq!(&Rgba([0, 0, 0, 255]), canvases[0].buffer().get_pixel(x, y));
+    }
+
+    #[test]
+    fn braille_background() {
+        // the background fills the whole cell, the dots are drawn over it
+        let (white, red): (Rgba<u8>, Rgba<u8>) = (Rgba([255, 255, 255, 255]), Rgba([200, 30, 30, 255]));
+        let mut canvas: RgbaImage = RgbaImage::new(SEGMENT_CONSTANT, SEGMENT_CONSTANT * 2);
+        draw_block(&mut canvas, (0, 0), block_shape('\u{2847}').unwrap(), white, Some(red));
+        assert_eq!(&red, canvas.get_pixel(0, 0));
+        assert_eq!(&red, canvas.get_pixel(SEGMENT_CONSTANT - 1, SEGMENT_CONSTANT * 2 - 1));
+        // middle of the top left dot
+        assert_eq!(&white, canvas.get_pixel(SEGMENT_CONSTANT / 4, SEGMENT_CONSTANT / 4));
+        assert!(canvas.pixels().all(|pixel| *pixel == white || *pixel == red));
+    }
+
+    #[test]
+    fn draw_block_cells() {
+        let (red, blue, white, black): (Rgba<u8>, Rgba<u8>, Rgba<u8>, Rgba<u8>) = (
+            Rgba([200, 30, 30, 255]),
+            Rgba([30, 30, 200, 255]),
+            Rgba([255, 255, 255, 255]),
+            Rgba([0, 0, 0, 255]),
+        );
+        let token = |token: char, rgb: (u8, u8, u8), bg_rgb: Option<(u8, u8, u8)>| -> AsciiToken {
+            return AsciiToken { token, rgb, bg_rgb, transparent: false, parent_img_width: 3, parent_img_height: 1 };
+        };
+        let tokens: Vec<AsciiToken> = vec![
+            // upper half block, a top left sextant and a full braille cell
+            token('\u{2580}', (200, 30, 30), Some((30, 30, 200))),
+            token('\u{1FB00}', (200, 30, 30), Some((30, 30, 200))),
+            token('\u{28FF}', (255, 255, 255), None),
+            token('\n', (0, 0, 0), None),
+        ];
+        let renderer: AsciiRenderer = AsciiRenderer::new().color(true).canvas((0, 0, 0));
+        let canvas: RgbaImage = draw_img(tokens, &renderer).unwrap();
+        // three cells plus a segment of padding on every side
+        assert_eq!((SEGMENT_CONSTANT * 5, SEGMENT_CONSTANT * 4), canvas.dimensions());
+        let (height, y): (u32, u32) = (SEGMENT_CONSTANT * 2, SEGMENT_CONSTANT);
+        let cell_x = |cell: u32| -> u32 { return SEGMENT_CONSTANT * (cell + 1) };
+
+        // the half block fills the top half in fg and the bottom half in bg
+        assert_eq!(&red, canvas.get_pixel(cell_x(0), y));
+        assert_eq!(&red, canvas.get_pixel(cell_x(0) + SEGMENT_CONSTANT - 1, y + height / 2 - 1));
+        assert_eq!(&blue, canvas.get_pixel(cell_x(0), y + height / 2));
+        assert_eq!(&blue, canvas.get_pixel(cell_x(0) + SEGMENT_CONSTANT - 1, y + height - 1));
+
+        // the sextant fills the top left third of its left column
+        assert_eq!(&red, canvas.get_pixel(cell_x(1), y));
+        assert_eq!(&red, canvas.get_pixel(cell_x(1) + SEGMENT_CONSTANT / 2 - 1, y + height / 3 - 1));
+        assert_eq!(&blue, canvas.get_pixel(cell_x(1) + SEGMENT_CONSTANT / 2, y));
+        assert_eq!(&blue, canvas.get_pixel(cell_x(1), y + height / 3));
+
+        // braille dots without a background leave the canvas showing between them
+        for row in 0..4 {
+            for col in 0..2 {
+                let (dot_width, dot_height): (u32, u32) = (SEGMENT_CONSTANT / 2, height / 4);
+                let center: (u32, u32) = (cell_x(2) + col * dot_width + dot_width / 2, y + row * dot_height + dot_height / 2);
+                assert_eq!(&white, canvas.get_pixel(center.0, center.1));
+            }
+        }
+        assert_eq!(&black, canvas.get_pixel(cell_x(2), y));
+        assert_eq!(&black, canvas.get_pixel(cell_x(2) + SEGMENT_CONSTANT - 1, y + height - 1));
+    }
+
+    #[test]
+    fn minimal_escapes() {
+        let token = |token: char, rgb: (u8, u8, u8), bg_rgb: Option<(u8, u8, u8)>| -> AsciiToken {
+            return AsciiToken { token, rgb, bg_rgb, transparent: false, parent_img_width: 4, parent_img_height: 2 };
+        };
+        let red: (u8, u8, u8) = (255, 0, 0);
+        let tokens: Vec<AsciiToken> = vec![
+            token('#', red, None),
+            token('#', red, None),
+            token(' ', (0, 0, 255), None),
+            token('#', red, Some((0, 0, 0))),
+            token('\n', (0, 0, 0), None),
+            token('#', red, None),
+            token('@', (0, 255, 0), None),
+            token('@', (0, 255, 0), None),
+            token('@', (0, 255, 0), None),
+            token('\n', (0, 0, 0), None),
+        ];
+        let mut out: Vec<u8> = Vec::new();
+        write_color_output(&mut out, tokens, Dither::None, ColorDepth::Truecolor, None).unwrap();
+        let expected: &str = concat!(
+            "\x1b[38;2;255;0;0m## \x1b[48;2;0;0;0m#\x1b[49m\n",
+            "#\x1b[38;2;0;255;0m@@@\n\x1b[0m",
+        );
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+
+        let mut out: Vec<u8> = Vec::new();
+        let mut writer: ColorWriter<&mut Vec<u8>> = ColorWriter::new(&mut out);
+        writer.write_char('#', TermColor::Ansi16(9), TermColor::Ansi256(21)).unwrap();
+        drop(writer);
+        assert_eq!("\x1b[91;48;5;21m#\x1b[0m", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn apng_round_trip() {
+        let renderer: AsciiRenderer = AsciiRenderer::new().scale_factor(8);
+        let frames: Vec<Frame> = [0u8, 255u8]
+            .iter()
+            .map(|shade| {
+                let canvas: RgbaImage = RgbaImage::from_pixel(8, 8, Rgba([*shade, 0, 0, 255]));
+                Frame::from_parts(canvas, 0, 0, Delay::from_numer_denom_ms(100, 1))
+            })
+            .collect();
+        let frames: Vec<AsciiFrame> =
+            convert_frames_to_ascii_tokens(frames, &renderer).unwrap();
+        let output: PathBuf = std::env::temp_dir().join("asciify-apng_round_trip.png");
+        save_apng(frames, &renderer.clone().color(true), &output).unwrap();
+
+        // the saved apng goes back through the animated pipeline
+        match process_file(output.to_string_lossy().to_string(), &renderer) {
+            Ok(ConvertedFile::GIF(frames)) => {
+                assert_eq!(2, frames.len());
+                assert_eq!(100, frames[1].delay.0 / frames[1].delay.1);
+            }
+            _ => panic!("Expected apng frames"),
+        };
+        std::fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn encode_img_to_writer() {
+        let renderer: AsciiRenderer = AsciiRenderer::new().scale_factor(8);
+        let tokens: Vec<AsciiToken> = match process_file(String::from("assets/rust_icon.png"), &renderer) {
+            Ok(ConvertedFile::IMAGE(tokens)) => tokens,
+            _ => panic!("Expected a still image"),
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_img(tokens, &renderer, ImageFormat::Png, &mut bytes).unwrap();
+        assert_eq!(ImageFormat::Png, image::guess_format(&bytes).unwrap());
     }
 }
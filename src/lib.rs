@@ -0,0 +1,35 @@
+//! Convert images and gifs into ascii art that can be printed to the terminal or
+//! saved as a new file.
+//!
+//! The [`AsciiRenderer`] builder is the main entry point, the lower level conversion
+//! and output functions are exposed through the [`convert_img`] and [`img_out`] modules.
+pub mod adjust;
+pub mod blocks;
+pub mod cell;
+pub mod convert_img;
+pub mod dither;
+pub mod edges;
+pub mod error;
+pub mod glyph_match;
+pub mod img_out;
+pub mod luminance;
+pub mod multi_image;
+pub mod palette;
+pub mod renderer;
+pub mod resample;
+pub mod terminal;
+pub mod utils;
+
+pub use adjust::{Adjustments, Equalize};
+pub use blocks::BlockMode;
+pub use cell::{CellStat, ColorTarget};
+pub use convert_img::ConvertedFile;
+pub use dither::Dither;
+pub use edges::EdgeDetector;
+pub use error::AsciifyError;
+pub use glyph_match::ShapeMetric;
+pub use luminance::Luminance;
+pub use renderer::{AsciiRenderer, Charset, OutputTarget};
+pub use resample::Filter;
+pub use terminal::{ColorMode, Fit};
+pub use utils::{AsciiFrame, AsciiToken};
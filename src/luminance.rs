@@ -0,0 +1,85 @@
+use clap::ValueEnum;
+use image::Rgba;
+
+/// Model used to turn a pixel's color into a single intensity value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Luminance {
+    /// Legacy unweighted average of the gamma encoded channels
+    Average,
+    /// ITU-R BT.601 luma weights on the gamma encoded channels
+    Rec601,
+    /// ITU-R BT.709 luma weights on the gamma encoded channels
+    #[default]
+    Rec709,
+    /// BT.709 weights applied to linear light, re-encoded with the sRGB curve
+    Linear,
+}
+
+/// Decodes an 8-bit sRGB channel value to linear light in the range 0.0 - 1.0
+pub(crate) fn srgb_to_linear(value: u8) -> f64 {
+    let v: f64 = value as f64 / 255.0;
+    if v <= 0.04045 {
+        return v / 12.92;
+    }
+    return ((v + 0.055) / 1.055).powf(2.4);
+}
+
+/// Encodes linear light in the range 0.0 - 1.0 with the sRGB curve
+fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        return value * 12.92;
+    }
+    return 1.055 * value.powf(1.0 / 2.4) - 0.055;
+}
+
+impl Luminance {
+    /// Returns the intensity of a pixel in the range 0.0 - 255.0
+    ///
+    /// # Arguments
+    ///
+    /// * 'pixel'     - Rgba pixel
+    pub fn intensity(&self, pixel: &Rgba<u8>) -> f64 {
+        let (r, g, b) = (pixel[0], pixel[1], pixel[2]);
+        match self {
+            // keep the truncating integer maths the original output was built with
+            Luminance::Average => return (r / 3 + g / 3 + b / 3) as f64,
+            Luminance::Rec601 => {
+                return 0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64;
+            }
+            Luminance::Rec709 => {
+                return 0.2126 * r as f64 + 0.7152 * g as f64 + 0.0722 * b as f64;
+            }
+            Luminance::Linear => {
+                let y: f64 = 0.2126 * srgb_to_linear(r)
+                    + 0.7152 * srgb_to_linear(g)
+                    + 0.0722 * srgb_to_linear(b);
+                return linear_to_srgb(y) * 255.0;
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn greys_are_unchanged() {
+        let grey: Rgba<u8> = Rgba([128, 128, 128, 255]);
+        for model in [Luminance::Rec601, Luminance::Rec709, Luminance::Linear] {
+            assert!((model.intensity(&grey) - 128.0).abs() < 0.5, "{:?}", model);
+        }
+        assert_eq!(126.0, Luminance::Average.intensity(&grey));
+    }
+
+    #[test]
+    fn green_is_brighter_than_blue() {
+        let green: Rgba<u8> = Rgba([0, 255, 0, 255]);
+        let blue: Rgba<u8> = Rgba([0, 0, 255, 255]);
+        for model in [Luminance::Rec601, Luminance::Rec709, Luminance::Linear] {
+            assert!(model.intensity(&green) > model.intensity(&blue) * 2.0, "{:?}", model);
+        }
+        assert_eq!(Luminance::Average.intensity(&green), Luminance::Average.intensity(&blue));
+    }
+}
@@ -1,34 +1,178 @@
-mod convert_img;
-mod img_out;
-mod utils;
-use utils::{AsciiArgs, is_supported_format};
+use asciify::convert_img::convert_bytes;
+use asciify::utils::{build_output_file_name, detect_format, output_format, AsciiArgs, STDIN_ARG};
+use asciify::terminal::{fit_area, is_light, light_background, terminal_size};
+use asciify::{AsciiRenderer, AsciifyError, Charset, ConvertedFile, OutputTarget};
 use clap::Parser;
-use img_out::{output_to_console, save};
+use image::ImageFormat;
+use std::{
+    fs,
+    io::{self, Read},
+    path::PathBuf,
+    process::ExitCode,
+};
 
-fn main() {
-    let args: AsciiArgs = AsciiArgs::parse();
+/// Build a renderer from the parsed commandline arguments
+///
+/// # Arguments
+///
+/// * 'args'    - Parsed commandline arguments
+fn build_renderer(args: &AsciiArgs) -> AsciiRenderer {
+    let charset: Charset = match &args.mapping {
+        Some(mapping) => Charset::Custom(mapping.chars().collect()),
+        None if args.detailed => Charset::Detailed,
+        None => Charset::Simple,
+    };
+    let mut renderer: AsciiRenderer = AsciiRenderer::new()
+        .charset(charset)
+        .luminance(args.luminance)
+        .cell_size(args.cell_width, args.cell_height)
+        .cell_stat(args.cell_stat)
+        .dither(args.dither)
+        .color(args.color)
+        .color_mode(args.color_mode)
+        .color_target(args.color_target);
+    if let Some(dither) = args.color_dither {
+        renderer = renderer.color_dither(dither);
+    }
+    if let Some(scale) = args.scale_factor {
+        renderer = renderer.scale_factor(scale);
+    }
+    if let Some(columns) = args.width {
+        renderer = renderer.width(columns);
+    }
+    if let Some(rows) = args.height {
+        renderer = renderer.height(rows);
+    }
+    if let Some(ratio) = args.char_aspect {
+        renderer = renderer.char_aspect(ratio);
+    }
+    renderer = renderer.stretch(args.stretch);
+    if let Some(filter) = args.filter {
+        renderer = renderer.filter(filter);
+    }
+    if let Some(sigma) = args.sharpen {
+        renderer = renderer.sharpen(sigma as f32);
+    }
+    if let Some(brightness) = args.brightness {
+        renderer = renderer.brightness(brightness);
+    }
+    if let Some(contrast) = args.contrast {
+        renderer = renderer.contrast(contrast);
+    }
+    if let Some(gamma) = args.gamma {
+        renderer = renderer.gamma(gamma);
+    }
+    if let Some(equalize) = args.equalize {
+        renderer = renderer.equalize(equalize);
+    }
+    renderer = renderer.auto_levels(args.auto_levels);
+    // dense characters stand for bright pixels on dark backgrounds, light ones need the
+    // ramp the other way around
+    let light: bool = match args.save {
+        true => is_light(args.canvas),
+        false => light_background().unwrap_or(false),
+    };
+    renderer = renderer.canvas(args.canvas).invert(args.invert != light);
+    // transparent pixels blend into whatever the output is shown on
+    let background: (u8, u8, u8) = match (args.background, args.save, light) {
+        (Some(background), _, _) => background,
+        (None, true, _) => args.canvas,
+        (None, false, true) => (255, 255, 255),
+        (None, false, false) => (0, 0, 0),
+    };
+    renderer = renderer.background(background).transparent(args.transparent);
+    if let Some(fit) = args.fit {
+        match terminal_size() {
+            Some(size) => {
+                let (columns, rows) = fit_area(size);
+                renderer = renderer.fit(fit, columns, rows);
+            }
+            None => eprintln!("asciify: couldn't find the terminal size, using the default scale factor"),
+        };
+    }
+    if let Some(detector) = args.edges {
+        renderer = renderer.edges(detector);
+    }
+    if let Some(metric) = args.shape {
+        renderer = renderer.shape(metric);
+    }
+    if let Some(font) = &args.font {
+        renderer = renderer.font(font);
+    }
+    if let Some(mode) = args.blocks {
+        renderer = renderer.blocks(mode);
+    }
+    return renderer;
+}
+
+/// Read the contents of a file argument, reading stdin for -
+///
+/// # Arguments
+///
+/// * 'path_arg'    - File path of the image to convert
+fn read_input(path_arg: &String) -> Result<Vec<u8>, AsciifyError> {
+    if path_arg == STDIN_ARG {
+        let mut bytes: Vec<u8> = Vec::new();
+        io::stdin().lock().read_to_end(&mut bytes)?;
+        return Ok(bytes);
+    }
+    return Ok(fs::read(path_arg)?);
+}
 
+/// Convert a single file and write it to the console, its output file or stdout
+///
+/// # Arguments
+///
+/// * 'renderer'    - Renderer built from the commandline arguments
+/// * 'path_arg'    - File path of the image to convert
+/// * 'args'        - Parsed commandline arguments
+fn run(renderer: &AsciiRenderer, path_arg: &String, args: &AsciiArgs) -> Result<(), AsciifyError> {
+    let bytes: Vec<u8> = read_input(path_arg)?;
+    let path_hint: Option<&String> = if path_arg == STDIN_ARG { None } else { Some(path_arg) };
+    let converted: ConvertedFile = convert_bytes(&bytes, path_hint, renderer)?;
     if !args.save {
-        for path_arg in args.files { 
-            output_to_console(
-                path_arg, 
-                args.scale_factor, 
-                args.detailed, 
-                args.color,
-                args.mapping.clone()).expect("Could not output to console");
-        }
+        return renderer.write(converted);
+    }
+
+    let animated: bool = matches!(converted, ConvertedFile::GIF(_));
+    let mut format: ImageFormat = output_format(detect_format(&bytes, path_hint)?, animated);
+    if animated && args.apng {
+        format = ImageFormat::Png;
+    }
+    let target: OutputTarget = if args.stdout {
+        OutputTarget::Stdout(format)
     } else {
-        // check format 
-        for path_arg in args.files {
-            if is_supported_format(&path_arg) {
-                save(path_arg, 
-                    args.detailed, 
-                    args.color, 
-                    args.mapping.clone(), 
-                    args.scale_factor).expect("Could not save to file");
-            } else {
-                panic!("Error: format not supported");
+        // stdin has no file name to build on
+        let name_arg: String = match path_hint {
+            Some(path) => path.clone(),
+            None => String::from("stdin"),
+        };
+        OutputTarget::File(PathBuf::from(build_output_file_name(&name_arg, format)?))
+    };
+    return renderer.clone().output(target).write(converted);
+}
+
+fn main() -> ExitCode {
+    let args: AsciiArgs = AsciiArgs::parse();
+    let renderer: AsciiRenderer = build_renderer(&args);
+
+    // report failures per file and carry on with the rest
+    let mut failed: bool = false;
+    for path_arg in &args.files {
+        match run(&renderer, path_arg, &args) {
+            Ok(()) => (),
+            // stop at Ctrl-C rather than moving on to the next file
+            Err(AsciifyError::Interrupted) => return ExitCode::from(130),
+            // whatever read the output has stopped, e.g. head, so there's nothing left to do
+            Err(AsciifyError::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe => return ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("asciify: {}: {}", path_arg, err);
+                failed = true;
             }
-        }
-    }    
+        };
+    }
+    if failed {
+        return ExitCode::FAILURE;
+    }
+    return ExitCode::SUCCESS;
 }
@@ -0,0 +1,283 @@
+use crate::error::AsciifyError;
+use image::{DynamicImage, ImageFormat};
+
+// size of the ico header and of each directory entry
+const ICO_HEADER_LEN: usize = 6;
+const ICO_ENTRY_LEN: usize = 16;
+
+// classic tiff header and ifd entry sizes (BigTIFF is decoded as a single image)
+const TIFF_HEADER_LEN: usize = 8;
+const TIFF_ENTRY_LEN: usize = 12;
+const TIFF_MAX_PAGES: usize = 1024;
+
+/// Reads a u16 from a byte slice with the given endianness
+fn read_u16(bytes: &[u8], offset: usize, little_endian: bool) -> Option<u16> {
+    let raw: [u8; 2] = bytes.get(offset..offset + 2)?.try_into().ok()?;
+    if little_endian {
+        return Some(u16::from_le_bytes(raw));
+    }
+    return Some(u16::from_be_bytes(raw));
+}
+
+/// Reads a u32 from a byte slice with the given endianness
+fn read_u32(bytes: &[u8], offset: usize, little_endian: bool) -> Option<u32> {
+    let raw: [u8; 4] = bytes.get(offset..offset + 4)?.try_into().ok()?;
+    if little_endian {
+        return Some(u32::from_le_bytes(raw));
+    }
+    return Some(u32::from_be_bytes(raw));
+}
+
+/// Returns whether a tiff is stored little endian, None if the bytes aren't a classic tiff
+fn tiff_little_endian(bytes: &[u8]) -> Option<bool> {
+    let little_endian: bool = match bytes.get(0..2) {
+        Some(b"II") => true,
+        Some(b"MM") => false,
+        _ => return None,
+    };
+    if read_u16(bytes, 2, little_endian) != Some(42) {
+        return None;
+    }
+    return Some(little_endian);
+}
+
+/// Returns the offset of each page's IFD in a multi-page tiff.
+///
+/// # Arguments
+///
+/// * 'bytes'     - Raw tiff file contents
+pub fn tiff_page_offsets(bytes: &[u8]) -> Vec<u32> {
+    let little_endian: bool = match tiff_little_endian(bytes) {
+        Some(little_endian) => little_endian,
+        None => return Vec::new(),
+    };
+
+    let mut offsets: Vec<u32> = Vec::new();
+    let mut next: u32 = read_u32(bytes, 4, little_endian).unwrap_or(0);
+    // guard against cyclic ifd chains in malformed files
+    while next as usize >= TIFF_HEADER_LEN && offsets.len() < TIFF_MAX_PAGES {
+        if offsets.contains(&next) {
+            break;
+        }
+        let entries: u16 = match read_u16(bytes, next as usize, little_endian) {
+            Some(entries) => entries,
+            None => break,
+        };
+        offsets.push(next);
+        let next_pointer: usize = next as usize + 2 + entries as usize * TIFF_ENTRY_LEN;
+        next = read_u32(bytes, next_pointer, little_endian).unwrap_or(0);
+    }
+    return offsets;
+}
+
+/// Decodes the given pages of a multi-page tiff.
+///
+/// Every IFD offset in a tiff is absolute, so each page is decoded by pointing the
+/// header's first IFD offset at that page's IFD and leaving the rest of the file untouched.
+/// All pages are decoded from a single copy of the file.
+///
+/// # Arguments
+///
+/// * 'bytes'     - Raw tiff file contents
+/// * 'offsets'   - IFD offset of each page to decode
+fn decode_tiff_pages(bytes: &[u8], offsets: &[u32]) -> Result<Vec<DynamicImage>, AsciifyError> {
+    let little_endian: bool = tiff_little_endian(bytes).unwrap_or(true);
+    let mut buffer: Vec<u8> = bytes.to_vec();
+    let mut images: Vec<DynamicImage> = Vec::new();
+    for offset in offsets {
+        let encoded: [u8; 4] = if little_endian {
+            offset.to_le_bytes()
+        } else {
+            offset.to_be_bytes()
+        };
+        buffer[4..8].copy_from_slice(&encoded);
+        let img: DynamicImage =
+            image::load_from_memory_with_format(&buffer, ImageFormat::Tiff).map_err(AsciifyError::decode)?;
+        images.push(img);
+    }
+    return Ok(images);
+}
+
+/// Splits an ico file into one standalone single-entry ico per image
+///
+/// # Arguments
+///
+/// * 'bytes'     - Raw ico file contents
+pub fn split_ico_entries(bytes: &[u8]) -> Vec<Vec<u8>> {
+    if bytes.len() < ICO_HEADER_LEN || bytes[0..4] != [0, 0, 1, 0] {
+        return Vec::new();
+    }
+    let count: usize = read_u16(bytes, 4, true).unwrap_or(0) as usize;
+
+    let mut entries: Vec<Vec<u8>> = Vec::new();
+    for i in 0..count {
+        let entry_start: usize = ICO_HEADER_LEN + i * ICO_ENTRY_LEN;
+        let entry: &[u8] = match bytes.get(entry_start..entry_start + ICO_ENTRY_LEN) {
+            Some(entry) => entry,
+            None => break,
+        };
+        let size: usize = read_u32(entry, 8, true).unwrap_or(0) as usize;
+        let offset: usize = read_u32(entry, 12, true).unwrap_or(0) as usize;
+        let data: &[u8] = match bytes.get(offset..offset.saturating_add(size)) {
+            Some(data) => data,
+            None => continue,
+        };
+
+        let data_offset: u32 = (ICO_HEADER_LEN + ICO_ENTRY_LEN) as u32;
+        let mut single: Vec<u8> = vec![0, 0, 1, 0, 1, 0];
+        single.extend_from_slice(&entry[0..12]);
+        single.extend_from_slice(&data_offset.to_le_bytes());
+        single.extend_from_slice(data);
+        entries.push(single);
+    }
+    return entries;
+}
+
+/// Decodes every image held in a container format, formats that can only hold a single
+/// image are decoded as is.
+///
+/// # Arguments
+///
+/// * 'bytes'     - Raw file contents
+/// * 'format'    - Detected format of the contents
+pub fn decode_images(bytes: &[u8], format: ImageFormat) -> Result<Vec<DynamicImage>, AsciifyError> {
+    match format {
+        ImageFormat::Tiff => {
+            let offsets: Vec<u32> = tiff_page_offsets(bytes);
+            if offsets.len() > 1 {
+                return decode_tiff_pages(bytes, &offsets);
+            }
+        }
+        ImageFormat::Ico => {
+            let entries: Vec<Vec<u8>> = split_ico_entries(bytes);
+            if entries.len() > 1 {
+                let mut images: Vec<DynamicImage> = Vec::new();
+                for entry in entries {
+                    let img: DynamicImage =
+                        image::load_from_memory_with_format(&entry, format).map_err(AsciifyError::decode)?;
+                    images.push(img);
+                }
+                return Ok(images);
+            }
+        }
+        _ => (),
+    };
+    let img: DynamicImage = image::load_from_memory_with_format(bytes, format).map_err(AsciifyError::decode)?;
+    return Ok(vec![img]);
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use image::{GenericImageView, GrayImage, Luma};
+    use std::io::Cursor;
+
+    /// Encodes an image into memory in the given format
+    fn encode(img: &DynamicImage, format: ImageFormat) -> Vec<u8> {
+        let mut bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        img.write_to(&mut bytes, format).unwrap();
+        return bytes.into_inner();
+    }
+
+    #[test]
+    fn split_ico() {
+        // build an ico holding a 16x16 and a 32x32 png
+        let small: Vec<u8> = encode(&DynamicImage::new_rgba8(16, 16), ImageFormat::Png);
+        let large: Vec<u8> = encode(&DynamicImage::new_rgba8(32, 32), ImageFormat::Png);
+        let mut ico: Vec<u8> = vec![0, 0, 1, 0, 2, 0];
+        let mut offset: u32 = (ICO_HEADER_LEN + 2 * ICO_ENTRY_LEN) as u32;
+        for (dim, data) in [(16u8, &small), (32u8, &large)] {
+            ico.extend_from_slice(&[dim, dim, 0, 0, 1, 0, 32, 0]);
+            ico.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            ico.extend_from_slice(&offset.to_le_bytes());
+            offset += data.len() as u32;
+        }
+        ico.extend_from_slice(&small);
+        ico.extend_from_slice(&large);
+
+        let images: Vec<DynamicImage> = decode_images(&ico, ImageFormat::Ico).unwrap();
+        let dims: Vec<(u32, u32)> = images.iter().map(|img| img.dimensions()).collect();
+        assert_eq!(vec![(16, 16), (32, 32)], dims);
+    }
+
+    #[test]
+    fn single_page_tiff() {
+        let img: DynamicImage = image::open("assets/mario.png").unwrap();
+        let tiff: Vec<u8> = encode(&img, ImageFormat::Tiff);
+        assert_eq!(1, tiff_page_offsets(&tiff).len());
+        let images: Vec<DynamicImage> = decode_images(&tiff, ImageFormat::Tiff).unwrap();
+        assert_eq!(img.dimensions(), images[0].dimensions());
+    }
+
+    /// Builds an uncompressed 8 bit grayscale tiff with one page per image, pixel data
+    /// first and the IFDs chained after it
+    fn encode_tiff_pages(pages: &[GrayImage], little_endian: bool) -> Vec<u8> {
+        let u16_bytes = |value: u16| -> [u8; 2] {
+            return if little_endian { value.to_le_bytes() } else { value.to_be_bytes() };
+        };
+        let u32_bytes = |value: u32| -> [u8; 4] {
+            return if little_endian { value.to_le_bytes() } else { value.to_be_bytes() };
+        };
+        let mut tiff: Vec<u8> = if little_endian { b"II".to_vec() } else { b"MM".to_vec() };
+        tiff.extend_from_slice(&u16_bytes(42));
+        tiff.extend_from_slice(&[0; 4]);
+
+        let mut strips: Vec<u32> = Vec::new();
+        for page in pages {
+            strips.push(tiff.len() as u32);
+            tiff.extend_from_slice(page.as_raw());
+        }
+        let mut next_pointer: usize = 4;
+        for (page, strip) in pages.iter().zip(strips) {
+            let ifd: u32 = tiff.len() as u32;
+            tiff[next_pointer..next_pointer + 4].copy_from_slice(&u32_bytes(ifd));
+            // (tag, type, value) with type 3 a short and 4 a long
+            let entries: [(u16, u16, u32); 9] = [
+                (256, 4, page.width()),
+                (257, 4, page.height()),
+                (258, 3, 8),
+                (259, 3, 1),
+                (262, 3, 1),
+                (273, 4, strip),
+                (277, 3, 1),
+                (278, 4, page.height()),
+                (279, 4, page.width() * page.height()),
+            ];
+            tiff.extend_from_slice(&u16_bytes(entries.len() as u16));
+            for (tag, kind, value) in entries {
+                tiff.extend_from_slice(&u16_bytes(tag));
+                tiff.extend_from_slice(&u16_bytes(kind));
+                tiff.extend_from_slice(&u32_bytes(1));
+                match kind {
+                    3 => {
+                        tiff.extend_from_slice(&u16_bytes(value as u16));
+                        tiff.extend_from_slice(&[0; 2]);
+                    }
+                    _ => tiff.extend_from_slice(&u32_bytes(value)),
+                };
+            }
+            next_pointer = tiff.len();
+            tiff.extend_from_slice(&[0; 4]);
+        }
+        return tiff;
+    }
+
+    #[test]
+    fn multi_page_tiff() {
+        let pages: Vec<GrayImage> = vec![
+            GrayImage::from_pixel(4, 3, Luma([0])),
+            GrayImage::from_pixel(5, 2, Luma([128])),
+            GrayImage::from_pixel(2, 6, Luma([255])),
+        ];
+        for little_endian in [true, false] {
+            let tiff: Vec<u8> = encode_tiff_pages(&pages, little_endian);
+            assert_eq!(3, tiff_page_offsets(&tiff).len());
+            let images: Vec<DynamicImage> = decode_images(&tiff, ImageFormat::Tiff).unwrap();
+            assert_eq!(pages.len(), images.len());
+            for (page, img) in pages.iter().zip(images) {
+                assert_eq!(page, &img.to_luma8());
+            }
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use crate::dither::{dither_grid, Dither};
+use crate::luminance::srgb_to_linear;
+use crate::terminal::ColorDepth;
+use crate::utils::{grid_dimensions, AsciiToken};
+use std::collections::HashMap;
+
+// xterm's default values for the 16 system colors
+const ANSI_SYSTEM: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+// channel values of the 6x6x6 color cube
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+// distance between neighbouring cube levels, used to scale ordered dithering
+const CUBE_SPREAD: f64 = 40.0;
+
+// rough distance between neighbouring system colors
+const SYSTEM_SPREAD: f64 = 128.0;
+
+/// Returns the rgb value of an xterm 256 color palette index
+///
+/// # Arguments
+///
+/// * 'index'     - Palette index
+pub fn ansi256_to_rgb(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..=15 => return ANSI_SYSTEM[index as usize],
+        16..=231 => {
+            let cube: usize = index as usize - 16;
+            return (
+                CUBE_LEVELS[cube / 36],
+                CUBE_LEVELS[(cube / 6) % 6],
+                CUBE_LEVELS[cube % 6],
+            );
+        }
+        _ => {
+            let grey: u8 = 8 + (index - 232) * 10;
+            return (grey, grey, grey);
+        }
+    };
+}
+
+/// Converts an sRGB color to OKLab, where the distance between two colors follows how
+/// different they look
+///
+/// # Arguments
+///
+/// * 'rgb'       - sRGB color
+pub fn oklab(rgb: (u8, u8, u8)) -> [f64; 3] {
+    let (r, g, b) = (srgb_to_linear(rgb.0), srgb_to_linear(rgb.1), srgb_to_linear(rgb.2));
+    let l: f64 = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m: f64 = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s: f64 = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    return [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ];
+}
+
+/// Colors of a terminal palette, matched to rgb values by their OKLab distance. Matches are
+/// cached since the same colors repeat across many tokens and frames.
+#[derive(Debug, Clone)]
+pub struct Palette {
+    // palette index and OKLab value of every color that can be picked
+    colors: Vec<(u8, [f64; 3])>,
+    // distance between neighbouring palette colors, scales ordered dithering
+    spread: f64,
+    cache: HashMap<(u8, u8, u8), u8>,
+}
+
+impl Palette {
+    /// The xterm 256 color palette. The 16 system colors are left out, terminal themes
+    /// change them so they can't be relied on.
+    pub fn ansi256() -> Palette {
+        return Palette::new((16..=255).collect(), CUBE_SPREAD);
+    }
+
+    /// The 16 system colors, with xterm's default values
+    pub fn ansi16() -> Palette {
+        return Palette::new((0..16).collect(), SYSTEM_SPREAD);
+    }
+
+    /// The palette colors are picked from at a color depth, None for truecolor
+    pub fn for_depth(depth: ColorDepth) -> Option<Palette> {
+        match depth {
+            ColorDepth::Ansi16 => return Some(Palette::ansi16()),
+            ColorDepth::Ansi256 => return Some(Palette::ansi256()),
+            ColorDepth::Truecolor => return None,
+        };
+    }
+
+    fn new(indices: Vec<u8>, spread: f64) -> Palette {
+        let colors: Vec<(u8, [f64; 3])> = indices
+            .into_iter()
+            .map(|index| (index, oklab(ansi256_to_rgb(index))))
+            .collect();
+        return Palette { colors, spread, cache: HashMap::new() };
+    }
+
+    /// Returns the index of the palette color that looks closest to an rgb value
+    ///
+    /// # Arguments
+    ///
+    /// * 'rgb'       - Color to match
+    pub fn nearest(&mut self, rgb: (u8, u8, u8)) -> u8 {
+        if let Some(index) = self.cache.get(&rgb) {
+            return *index;
+        }
+        let lab: [f64; 3] = oklab(rgb);
+        let distance = |color: &[f64; 3]| -> f64 {
+            return (0..3).map(|c| (color[c] - lab[c]).powi(2)).sum::<f64>();
+        };
+        let index: u8 = self
+            .colors
+            .iter()
+            .min_by(|a, b| distance(&a.1).total_cmp(&distance(&b.1)))
+            .map_or(0, |(index, _)| *index);
+        self.cache.insert(rgb, index);
+        return index;
+    }
+
+    /// Maps a color of each token onto the palette, dithering the colors across neighbouring
+    /// cells. Newline tokens are mapped to index 0.
+    ///
+    /// # Arguments
+    ///
+    /// * 'tokens'    - Ascii tokens with rows separated by newline tokens
+    /// * 'dither'    - Dithering mode
+    /// * 'color'     - Picks the color of a token that is mapped
+    fn map_tokens<F>(&mut self, tokens: &[AsciiToken], dither: Dither, color: F) -> Vec<u8>
+    where
+        F: Fn(&AsciiToken) -> (u8, u8, u8),
+    {
+        let (width, _) = grid_dimensions(tokens);
+        let cells: Vec<[f64; 3]> = tokens
+            .iter()
+            .filter(|token| token.token != '\n')
+            .map(|token| {
+                let (r, g, b) = color(token);
+                return [r as f64, g as f64, b as f64];
+            })
+            .collect();
+        let spread: f64 = self.spread;
+        let indices: Vec<usize> = dither_grid(&cells, width, dither, spread, |color| {
+            let clamped: [u8; 3] = color.map(|channel| channel.round().clamp(0.0, 255.0) as u8);
+            let index: u8 = self.nearest((clamped[0], clamped[1], clamped[2]));
+            let (r, g, b) = ansi256_to_rgb(index);
+            return (index as usize, [r as f64, g as f64, b as f64]);
+        });
+
+        let mut cell_indices = indices.into_iter();
+        return tokens
+            .iter()
+            .map(|token| match token.token {
+                '\n' => 0,
+                _ => cell_indices.next().unwrap_or(0) as u8,
+            })
+            .collect();
+    }
+
+    /// Maps the color of each token onto the palette, dithering the colors across
+    /// neighbouring cells. Newline tokens are mapped to index 0.
+    ///
+    /// # Arguments
+    ///
+    /// * 'tokens'    - Ascii tokens with rows separated by newline tokens
+    /// * 'dither'    - Dithering mode
+    pub fn indices(&mut self, tokens: &[AsciiToken], dither: Dither) -> Vec<u8> {
+        return self.map_tokens(tokens, dither, |token| token.rgb);
+    }
+
+    /// Maps the background color of each token onto the palette, tokens without a background
+    /// are dithered with their foreground color
+    ///
+    /// # Arguments
+    ///
+    /// * 'tokens'    - Ascii tokens with rows separated by newline tokens
+    /// * 'dither'    - Dithering mode
+    pub fn bg_indices(&mut self, tokens: &[AsciiToken], dither: Dither) -> Vec<u8> {
+        return self.map_tokens(tokens, dither, |token| token.bg_rgb.unwrap_or(token.rgb));
+    }
+}
+
+/// Returns the escape codes selecting a system color, 30 - 37 and 90 - 97 for the foreground
+/// and 40 - 47 and 100 - 107 for the background
+///
+/// # Arguments
+///
+/// * 'index'       - System color index
+/// * 'background'  - Whether to set the background color
+pub fn ansi16_code(index: u8, background: bool) -> u8 {
+    let base: u8 = match (index < 8, background) {
+        (true, false) => 30,
+        (true, true) => 40,
+        (false, false) => 90 - 8,
+        (false, true) => 100 - 8,
+    };
+    return base + index.min(15);
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn palette_values() {
+        assert_eq!((0, 0, 0), ansi256_to_rgb(16));
+        assert_eq!((255, 255, 255), ansi256_to_rgb(231));
+        assert_eq!((95, 135, 175), ansi256_to_rgb(16 + 36 + 2 * 6 + 3));
+        assert_eq!((8, 8, 8), ansi256_to_rgb(232));
+        assert_eq!((238, 238, 238), ansi256_to_rgb(255));
+    }
+
+    #[test]
+    fn system_colors() {
+        let mut palette: Palette = Palette::ansi16();
+        assert_eq!(0, palette.nearest((10, 10, 10)));
+        assert_eq!(1, palette.nearest((190, 20, 10)));
+        assert_eq!(9, palette.nearest((250, 30, 30)));
+        assert_eq!(15, palette.nearest((250, 250, 250)));
+        assert_eq!(31, ansi16_code(1, false));
+        assert_eq!(44, ansi16_code(4, true));
+        assert_eq!(97, ansi16_code(15, false));
+        assert_eq!(100, ansi16_code(8, true));
+    }
+
+    #[test]
+    fn perceptual_matching() {
+        let mut palette: Palette = Palette::ansi256();
+        // exact palette colors match themselves, the system colors are never picked
+        assert_eq!(16 + 36 + 2 * 6 + 3, palette.nearest((95, 135, 175)));
+        assert_eq!(16, palette.nearest((0, 0, 0)));
+        // a dark grey stays on the grey ramp rather than turning blue or green
+        let (r, g, b) = ansi256_to_rgb(palette.nearest((50, 50, 50)));
+        assert!(r == g && g == b);
+        // a skin tone keeps its hue
+        let (r, g, b) = ansi256_to_rgb(palette.nearest((224, 172, 105)));
+        assert!(r > g && g > b);
+        assert_eq!(Some(&palette.nearest((224, 172, 105))), palette.cache.get(&(224, 172, 105)));
+
+        let lab: [f64; 3] = oklab((255, 255, 255));
+        assert!((lab[0] - 1.0).abs() < 0.001 && lab[1].abs() < 0.001 && lab[2].abs() < 0.001);
+    }
+}
@@ -0,0 +1,476 @@
+use crate::convert_img::{
+    convert_bytes, convert_image, process_file, ConvertedFile, ASCII_DETAILED, ASCII_SIMPLE,
+};
+use crate::adjust::{Adjustments, Equalize};
+use crate::blocks::BlockMode;
+use crate::cell::{CellStat, ColorTarget};
+use crate::dither::Dither;
+use crate::edges::EdgeDetector;
+use crate::error::AsciifyError;
+use crate::glyph_match::ShapeMetric;
+use crate::img_out::write_output;
+use crate::luminance::Luminance;
+use crate::resample::Filter;
+use crate::terminal::{ColorMode, Fit};
+use crate::utils::AsciiToken;
+use image::{DynamicImage, ImageFormat};
+use std::path::PathBuf;
+
+pub const DEFAULT_SCALE: u32 = 72;
+
+// characters are roughly twice as tall as they are wide
+pub const DEFAULT_CELL_SIZE: (u32, u32) = (1, 2);
+
+/// Set of characters used to encode pixel intensities, ordered from darkest to brightest
+#[derive(Debug, Clone, PartialEq)]
+pub enum Charset {
+    Simple,
+    Detailed,
+    Custom(Vec<char>),
+}
+
+impl Charset {
+    /// Returns the character ramp for the charset
+    pub fn chars(&self) -> Vec<char> {
+        match self {
+            Charset::Simple => ASCII_SIMPLE.to_vec(),
+            Charset::Detailed => ASCII_DETAILED.to_vec(),
+            Charset::Custom(chars) => chars.clone(),
+        }
+    }
+}
+
+/// Destination for rendered ascii output
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputTarget {
+    /// Print text (or ANSI colored text) to stdout
+    Console,
+    /// Save as an image, gif or apng picked from the file extension
+    File(PathBuf),
+    /// Write the encoded image to stdout, frames are written as an apng for
+    /// `ImageFormat::Png` and as a gif otherwise
+    Stdout(ImageFormat),
+}
+
+/// Builder used to configure and run the ascii conversion pipeline.
+///
+/// ```no_run
+/// use asciify::{AsciiRenderer, Charset, OutputTarget};
+///
+/// let renderer = AsciiRenderer::new()
+///     .scale_factor(48)
+///     .charset(Charset::Detailed)
+///     .color(true)
+///     .output(OutputTarget::Console);
+/// renderer.render_path("assets/ferris.jpg").unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct AsciiRenderer {
+    pub(crate) scale_factor: u32,
+    pub(crate) width: Option<u32>,
+    pub(crate) height: Option<u32>,
+    pub(crate) stretch: bool,
+    pub(crate) char_aspect: Option<f64>,
+    pub(crate) filter: Option<Filter>,
+    pub(crate) sharpen: Option<f32>,
+    pub(crate) adjust: Adjustments,
+    pub(crate) charset: Charset,
+    pub(crate) luminance: Luminance,
+    pub(crate) cell_size: (u32, u32),
+    pub(crate) cell_stat: CellStat,
+    pub(crate) dither: Dither,
+    pub(crate) color_dither: Option<Dither>,
+    pub(crate) edges: Option<EdgeDetector>,
+    pub(crate) shape: Option<ShapeMetric>,
+    pub(crate) font: Option<PathBuf>,
+    pub(crate) blocks: Option<BlockMode>,
+    pub(crate) invert: bool,
+    pub(crate) canvas: (u8, u8, u8),
+    pub(crate) background: (u8, u8, u8),
+    pub(crate) transparent: bool,
+    pub(crate) color: bool,
+    pub(crate) color_mode: ColorMode,
+    pub(crate) color_target: ColorTarget,
+    pub(crate) target: OutputTarget,
+}
+
+impl Default for AsciiRenderer {
+    fn default() -> Self {
+        return AsciiRenderer {
+            scale_factor: DEFAULT_SCALE,
+            width: None,
+            height: None,
+            stretch: false,
+            char_aspect: None,
+            filter: None,
+            sharpen: None,
+            adjust: Adjustments::default(),
+            charset: Charset::Simple,
+            luminance: Luminance::default(),
+            cell_size: DEFAULT_CELL_SIZE,
+            cell_stat: CellStat::default(),
+            dither: Dither::default(),
+            color_dither: None,
+            edges: None,
+            shape: None,
+            font: None,
+            blocks: None,
+            invert: false,
+            canvas: (0, 0, 0),
+            background: (0, 0, 0),
+            transparent: false,
+            color: false,
+            color_mode: ColorMode::default(),
+            color_target: ColorTarget::default(),
+            target: OutputTarget::Console,
+        };
+    }
+}
+
+impl AsciiRenderer {
+    pub fn new() -> Self {
+        return AsciiRenderer::default();
+    }
+
+    /// Maximum bound used for the longest side of the image
+    pub fn scale_factor(mut self, scale_factor: u32) -> Self {
+        self.scale_factor = scale_factor;
+        return self;
+    }
+
+    /// Number of characters in each row of output, replaces the scale factor. Without a
+    /// height the number of rows follows from the image's aspect ratio.
+    pub fn width(mut self, columns: u32) -> Self {
+        self.width = Some(columns);
+        return self;
+    }
+
+    /// Number of rows of output, replaces the scale factor. Without a width the number of
+    /// characters in a row follows from the image's aspect ratio.
+    pub fn height(mut self, rows: u32) -> Self {
+        self.height = Some(rows);
+        return self;
+    }
+
+    /// Fill the width and height exactly, ignoring the image's aspect ratio. Otherwise the image
+    /// is shrunk to fit inside both when both are set.
+    pub fn stretch(mut self, stretch: bool) -> Self {
+        self.stretch = stretch;
+        return self;
+    }
+
+    /// Height of a character divided by its width, defaults to the ratio of the cell size
+    pub fn char_aspect(mut self, ratio: f64) -> Self {
+        self.char_aspect = Some(ratio);
+        return self;
+    }
+
+    /// Filter used to scale images down, by default nearest is used for pixel art and gaussian
+    /// for everything else
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = Some(filter);
+        return self;
+    }
+
+    /// Sharpen the scaled image with an unsharp mask of the given radius in pixels
+    pub fn sharpen(mut self, sigma: f32) -> Self {
+        self.sharpen = Some(sigma);
+        return self;
+    }
+
+    /// Offset added to every color channel of the scaled image, -1.0 - 1.0
+    pub fn brightness(mut self, brightness: f64) -> Self {
+        self.adjust.brightness = brightness;
+        return self;
+    }
+
+    /// Factor the distance of every color channel from mid grey is scaled by
+    pub fn contrast(mut self, contrast: f64) -> Self {
+        self.adjust.contrast = contrast;
+        return self;
+    }
+
+    /// Gamma correction of the scaled image, values above 1.0 brighten the mid tones
+    pub fn gamma(mut self, gamma: f64) -> Self {
+        self.adjust.gamma = gamma;
+        return self;
+    }
+
+    /// Stretch the intensities of the scaled image to cover black to white
+    pub fn auto_levels(mut self, auto_levels: bool) -> Self {
+        self.adjust.auto_levels = auto_levels;
+        return self;
+    }
+
+    /// Equalize the histogram of the scaled image, the statistics of animations are gathered
+    /// over every frame
+    pub fn equalize(mut self, equalize: Equalize) -> Self {
+        self.adjust.equalize = Some(equalize);
+        return self;
+    }
+
+    /// Scale images to fill a terminal of the given number of columns and rows instead of
+    /// bounding them by the scale factor, see `terminal::terminal_size`
+    pub fn fit(mut self, fit: Fit, columns: u32, rows: u32) -> Self {
+        (self.width, self.height) = match fit {
+            Fit::Width => (Some(columns), None),
+            Fit::Height => (None, Some(rows)),
+            Fit::Both => (Some(columns), Some(rows)),
+        };
+        return self;
+    }
+
+    /// Characters used to encode pixel intensities
+    pub fn charset(mut self, charset: Charset) -> Self {
+        self.charset = charset;
+        return self;
+    }
+
+    /// Model used to compute pixel intensities
+    pub fn luminance(mut self, luminance: Luminance) -> Self {
+        self.luminance = luminance;
+        return self;
+    }
+
+    /// Width and height in pixels of the area of the scaled image each character covers
+    pub fn cell_size(mut self, width: u32, height: u32) -> Self {
+        self.cell_size = (width, height);
+        return self;
+    }
+
+    /// Statistic used to reduce the pixels of a cell to a single character
+    pub fn cell_stat(mut self, cell_stat: CellStat) -> Self {
+        self.cell_stat = cell_stat;
+        return self;
+    }
+
+    /// Dithering used when mapping intensities to characters, and colors to the 256 or 16
+    /// color palette when truecolor isn't available
+    pub fn dither(mut self, dither: Dither) -> Self {
+        self.dither = dither;
+        return self;
+    }
+
+    /// Dithering used when mapping colors to the 256 or 16 color palette, in place of the
+    /// dithering of the characters
+    pub fn color_dither(mut self, dither: Dither) -> Self {
+        self.color_dither = Some(dither);
+        return self;
+    }
+
+    /// Draw strong edges with orientation matching characters (`| / - \ _`), the remaining
+    /// cells are filled from the character ramp
+    pub fn edges(mut self, detector: EdgeDetector) -> Self {
+        self.edges = Some(detector);
+        return self;
+    }
+
+    /// Pick each character by matching the shape of its rendered glyph against the cell,
+    /// instead of by brightness alone
+    pub fn shape(mut self, metric: ShapeMetric) -> Self {
+        self.shape = Some(metric);
+        return self;
+    }
+
+    /// Font file used to match glyph shapes and draw saved output, defaults to the
+    /// bundled Roboto font
+    pub fn font(mut self, path: impl Into<PathBuf>) -> Self {
+        self.font = Some(path.into());
+        return self;
+    }
+
+    /// Draw with unicode block characters instead of ascii characters, in color each character
+    /// shows its pixels with separate foreground and background colors
+    pub fn blocks(mut self, mode: BlockMode) -> Self {
+        self.blocks = Some(mode);
+        return self;
+    }
+
+    /// Reverse the character ramp so bright pixels get sparse characters, for output shown on a
+    /// light background
+    pub fn invert(mut self, invert: bool) -> Self {
+        self.invert = invert;
+        return self;
+    }
+
+    /// Background color of saved output, characters are drawn in black rather than white on
+    /// light colors. Combine a light canvas with `invert`.
+    pub fn canvas(mut self, rgb: (u8, u8, u8)) -> Self {
+        self.canvas = rgb;
+        return self;
+    }
+
+    /// Color transparent pixels are composited over before they're turned into characters
+    pub fn background(mut self, rgb: (u8, u8, u8)) -> Self {
+        self.background = rgb;
+        return self;
+    }
+
+    /// Leave mostly transparent cells as spaces without color, so they show the background of
+    /// the terminal
+    pub fn transparent(mut self, transparent: bool) -> Self {
+        self.transparent = transparent;
+        return self;
+    }
+
+    /// Render output with the original colors of the image
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        return self;
+    }
+
+    /// How many colors color output is printed to the terminal with, by default it's detected
+    /// from the terminal and left out when stdout isn't one
+    pub fn color_mode(mut self, mode: ColorMode) -> Self {
+        self.color_mode = mode;
+        return self;
+    }
+
+    /// Whether color output colors the characters, their cells or both, for mosaics that show
+    /// color even with sparse characters
+    pub fn color_target(mut self, target: ColorTarget) -> Self {
+        self.color_target = target;
+        return self;
+    }
+
+    /// Where rendered output is written to
+    pub fn output(mut self, target: OutputTarget) -> Self {
+        self.target = target;
+        return self;
+    }
+
+    /// Returns the dithering used when colors are mapped to a palette
+    pub fn palette_dither(&self) -> Dither {
+        return self.color_dither.unwrap_or(self.dither);
+    }
+
+    /// Returns the character ramp used for the conversion, from the characters drawn for dark
+    /// pixels to the ones drawn for bright pixels
+    pub fn ascii_table(&self) -> Vec<char> {
+        let mut chars: Vec<char> = self.charset.chars();
+        if self.invert {
+            chars.reverse();
+        }
+        return chars;
+    }
+
+    /// Converts an in-memory image into ascii tokens
+    ///
+    /// # Arguments
+    ///
+    /// * 'img'     - Decoded image
+    pub fn convert_image(&self, img: DynamicImage) -> Result<Vec<AsciiToken>, AsciifyError> {
+        return convert_image(img, self);
+    }
+
+    /// Converts encoded image or gif data into ascii tokens
+    ///
+    /// # Arguments
+    ///
+    /// * 'bytes'   - Raw file contents
+    pub fn convert_bytes(&self, bytes: &[u8]) -> Result<ConvertedFile, AsciifyError> {
+        return convert_bytes(bytes, None, self);
+    }
+
+    /// Reads a file and converts it into ascii tokens
+    ///
+    /// # Arguments
+    ///
+    /// * 'path'    - File path to the image
+    pub fn convert_path(&self, path: &str) -> Result<ConvertedFile, AsciifyError> {
+        return process_file(String::from(path), self);
+    }
+
+    /// Writes converted data to the configured output target
+    ///
+    /// # Arguments
+    ///
+    /// * 'converted'   - Previously converted image or gif
+    pub fn write(&self, converted: ConvertedFile) -> Result<(), AsciifyError> {
+        return write_output(converted, self);
+    }
+
+    /// Converts a file and writes it to the configured output target
+    ///
+    /// # Arguments
+    ///
+    /// * 'path'    - File path to the image
+    pub fn render_path(&self, path: &str) -> Result<(), AsciifyError> {
+        return self.write(self.convert_path(path)?);
+    }
+
+    /// Converts encoded image data and writes it to the configured output target
+    ///
+    /// # Arguments
+    ///
+    /// * 'bytes'   - Raw file contents
+    pub fn render_bytes(&self, bytes: &[u8]) -> Result<(), AsciifyError> {
+        return self.write(self.convert_bytes(bytes)?);
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn convert_bytes_matches_path() {
+        let renderer: AsciiRenderer = AsciiRenderer::new().scale_factor(20);
+        let bytes: Vec<u8> = std::fs::read("assets/ferris.jpg").unwrap();
+        let from_bytes: String = match renderer.convert_bytes(&bytes).unwrap() {
+            ConvertedFile::IMAGE(tokens) => tokens.iter().map(|t| t.token).collect(),
+            _ => panic!("Expected a still image"),
+        };
+        let from_path: String = match renderer.convert_path("assets/ferris.jpg").unwrap() {
+            ConvertedFile::IMAGE(tokens) => tokens.iter().map(|t| t.token).collect(),
+            _ => panic!("Expected a still image"),
+        };
+        assert_eq!(from_bytes, from_path);
+    }
+
+    #[test]
+    fn custom_charset() {
+        let charset: Charset = Charset::Custom(vec!['-', '}']);
+        let renderer: AsciiRenderer = AsciiRenderer::new().charset(charset);
+        assert_eq!(vec!['-', '}'], renderer.ascii_table());
+        assert_eq!(vec!['}', '-'], renderer.invert(true).ascii_table());
+    }
+
+    #[test]
+    fn empty_charset_error() {
+        let renderer: AsciiRenderer = AsciiRenderer::new().charset(Charset::Custom(Vec::new()));
+        match renderer.convert_path("assets/ferris.jpg") {
+            Err(AsciifyError::EmptyCharset) => (),
+            other => panic!("Expected an empty charset error, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn invalid_setting_error() {
+        let renderers: [(&str, AsciiRenderer); 7] = [
+            ("brightness", AsciiRenderer::new().brightness(f64::NAN)),
+            ("brightness", AsciiRenderer::new().brightness(1.5)),
+            ("contrast", AsciiRenderer::new().contrast(f64::INFINITY)),
+            ("contrast", AsciiRenderer::new().contrast(-1.0)),
+            ("gamma", AsciiRenderer::new().gamma(0.0)),
+            ("sharpen", AsciiRenderer::new().sharpen(f32::NAN)),
+            ("sharpen", AsciiRenderer::new().sharpen(f32::INFINITY)),
+        ];
+        for (setting, renderer) in renderers {
+            match renderer.convert_path("assets/ferris.jpg") {
+                Err(AsciifyError::InvalidSetting(name)) => assert_eq!(setting, name),
+                other => panic!("Expected an invalid {} error, got {:?}", setting, other),
+            };
+        }
+    }
+
+    #[test]
+    fn missing_file_error() {
+        let renderer: AsciiRenderer = AsciiRenderer::new();
+        match renderer.convert_path("assets/does_not_exist.png") {
+            Err(AsciifyError::Io(_)) => (),
+            other => panic!("Expected an I/O error, got {:?}", other),
+        };
+    }
+}
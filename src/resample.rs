@@ -0,0 +1,103 @@
+use clap::ValueEnum;
+use image::{imageops::FilterType, DynamicImage, GenericImageView, Rgba};
+use std::collections::HashSet;
+
+// images with at most this many distinct colors are treated as pixel art, photos and
+// anti-aliased drawings have thousands
+const PIXEL_ART_COLORS: usize = 64;
+
+// smallest per channel difference unsharp masking amplifies, keeps flat areas from getting noisy
+const SHARPEN_THRESHOLD: i32 = 2;
+
+/// Filter used to scale images down to the character grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Filter {
+    /// Nearest neighbour, keeps the hard edges and exact colors of pixel art
+    Nearest,
+    /// Linear filter
+    Triangle,
+    /// Cubic filter, sharper than triangle
+    #[value(name = "catmullrom")]
+    CatmullRom,
+    /// Gaussian filter, smooth but blurs fine detail
+    Gaussian,
+    /// Lanczos filter with a window of 3, the sharpest
+    Lanczos3,
+}
+
+impl Filter {
+    /// Returns the matching filter of the image crate
+    pub fn filter_type(&self) -> FilterType {
+        match self {
+            Filter::Nearest => return FilterType::Nearest,
+            Filter::Triangle => return FilterType::Triangle,
+            Filter::CatmullRom => return FilterType::CatmullRom,
+            Filter::Gaussian => return FilterType::Gaussian,
+            Filter::Lanczos3 => return FilterType::Lanczos3,
+        };
+    }
+
+    /// Picks the filter for an image when none was chosen, nearest for pixel art and gaussian
+    /// for everything else
+    ///
+    /// # Arguments
+    ///
+    /// * 'img'       - Image before scaling
+    pub fn detect<I: GenericImageView<Pixel = Rgba<u8>>>(img: &I) -> Filter {
+        if is_pixel_art(img) {
+            return Filter::Nearest;
+        }
+        return Filter::Gaussian;
+    }
+}
+
+/// Returns true if an image only uses a handful of flat colors
+///
+/// # Arguments
+///
+/// * 'img'       - Image to inspect
+pub fn is_pixel_art<I: GenericImageView<Pixel = Rgba<u8>>>(img: &I) -> bool {
+    let mut colors: HashSet<Rgba<u8>> = HashSet::new();
+    for (_, _, pixel) in img.pixels() {
+        if colors.insert(pixel) && colors.len() > PIXEL_ART_COLORS {
+            return false;
+        }
+    }
+    return true;
+}
+
+/// Sharpens a scaled image with an unsharp mask
+///
+/// # Arguments
+///
+/// * 'img'       - Scaled image
+/// * 'sigma'     - Radius of the blur subtracted from the image, in pixels of the scaled image
+pub fn sharpen(img: DynamicImage, sigma: f32) -> DynamicImage {
+    if sigma <= 0.0 {
+        return img;
+    }
+    return img.unsharpen(sigma, SHARPEN_THRESHOLD);
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use image::RgbaImage;
+
+    #[test]
+    fn detects_pixel_art() {
+        let sprite: DynamicImage = DynamicImage::ImageRgba8(RgbaImage::from_fn(32, 32, |x, y| {
+            match (x / 4 + y / 4) % 3 {
+                0 => Rgba([255, 0, 0, 255]),
+                1 => Rgba([0, 0, 0, 0]),
+                _ => Rgba([250, 200, 150, 255]),
+            }
+        }));
+        assert_eq!(Filter::Nearest, Filter::detect(&sprite));
+
+        let gradient: DynamicImage =
+            DynamicImage::ImageRgba8(RgbaImage::from_fn(32, 32, |x, y| Rgba([(x * 8) as u8, (y * 8) as u8, 0, 255])));
+        assert_eq!(Filter::Gaussian, Filter::detect(&gradient));
+    }
+}
@@ -0,0 +1,534 @@
+use clap::ValueEnum;
+use std::{
+    env, fs,
+    io::{self, IsTerminal, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+#[cfg(unix)]
+use std::{
+    fs::{File, OpenOptions},
+    io::Read,
+    os::unix::io::AsRawFd,
+};
+
+// how long to wait for the terminal to answer a query
+#[cfg(unix)]
+const QUERY_TIMEOUT_MS: libc::c_int = 100;
+
+// switches to the alternate screen and hides the cursor
+const ENTER_PLAYBACK: &str = "\x1b[?1049h\x1b[?25l";
+// resets colors, shows the cursor and switches back to the main screen
+const LEAVE_PLAYBACK: &str = "\x1b[0m\x1b[?25h\x1b[?1049l";
+// begins a synchronized update (mode 2026) and moves the cursor to the top left corner
+const BEGIN_FRAME: &str = "\x1b[?2026h\x1b[H";
+// clears whatever the previous frame left below the cursor and ends the synchronized update
+const END_FRAME: &str = "\x1b[J\x1b[?2026l";
+
+// how often a frame delay checks whether playback was interrupted
+const INTERRUPT_POLL_MS: u64 = 10;
+
+// set by the SIGINT handler while frames are played
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Terminal dimension the output is scaled to fill
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Fit {
+    /// Fill the width of the terminal, tall images may scroll
+    Width,
+    /// Fill the height of the terminal, wide images may wrap
+    Height,
+    /// Fill as much of the terminal as possible without wrapping or scrolling
+    Both,
+}
+
+/// How color output is sent to the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ColorMode {
+    /// Detect the colors the terminal supports, no color when stdout isn't a terminal or
+    /// NO_COLOR is set
+    #[default]
+    Auto,
+    /// Never print color escapes
+    Never,
+    /// The 16 system colors of the terminal
+    #[value(name = "16")]
+    Ansi16,
+    /// The xterm 256 color palette
+    #[value(name = "256")]
+    Ansi256,
+    /// 24 bit rgb colors
+    Truecolor,
+}
+
+/// Number of colors color output is printed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorDepth {
+    Ansi16,
+    Ansi256,
+    Truecolor,
+}
+
+// index of the max_colors number in a compiled terminfo entry
+const TERMINFO_COLORS: usize = 13;
+
+/// Returns the number of colors to print with, None when output shouldn't be colored
+///
+/// # Arguments
+///
+/// * 'mode'      - Requested color mode, the modes naming a palette are used as is
+pub fn color_depth(mode: ColorMode) -> Option<ColorDepth> {
+    match mode {
+        ColorMode::Auto => {
+            return detect_depth(|name| env::var(name).ok(), io::stdout().is_terminal(), terminfo_colors);
+        }
+        ColorMode::Never => return None,
+        ColorMode::Ansi16 => return Some(ColorDepth::Ansi16),
+        ColorMode::Ansi256 => return Some(ColorDepth::Ansi256),
+        ColorMode::Truecolor => return Some(ColorDepth::Truecolor),
+    };
+}
+
+/// Works out the colors a terminal supports from its environment. NO_COLOR turns color off,
+/// CLICOLOR_FORCE turns it on when stdout isn't a terminal, COLORTERM and TERM name truecolor
+/// and 256 color terminals and the terminfo entry of TERM gives the number of colors otherwise.
+///
+/// # Arguments
+///
+/// * 'var'       - Looks up an environment variable
+/// * 'tty'       - Whether stdout is a terminal
+/// * 'terminfo'  - Looks up the number of colors of a terminal in the terminfo database
+fn detect_depth<V, T>(var: V, tty: bool, terminfo: T) -> Option<ColorDepth>
+where
+    V: Fn(&str) -> Option<String>,
+    T: Fn(&str) -> Option<u32>,
+{
+    let non_empty = |name: &str| -> Option<String> { return var(name).filter(|value| !value.is_empty()) };
+    if non_empty("NO_COLOR").is_some() {
+        return None;
+    }
+    let forced: bool = non_empty("CLICOLOR_FORCE").is_some_and(|value| value != "0");
+    let term: String = var("TERM").unwrap_or_default();
+    if !forced && (!tty || term == "dumb") {
+        return None;
+    }
+
+    let colorterm: String = var("COLORTERM").unwrap_or_default().to_lowercase();
+    if colorterm == "truecolor" || colorterm == "24bit" {
+        return Some(ColorDepth::Truecolor);
+    }
+    if term.ends_with("-direct") || term.contains("truecolor") || term.contains("24bit") {
+        return Some(ColorDepth::Truecolor);
+    }
+    if term.contains("256color") {
+        return Some(ColorDepth::Ansi256);
+    }
+    match terminfo(&term) {
+        Some(colors) if colors >= 1 << 24 => return Some(ColorDepth::Truecolor),
+        Some(colors) if colors >= 256 => return Some(ColorDepth::Ansi256),
+        Some(colors) if colors >= 8 => return Some(ColorDepth::Ansi16),
+        Some(_) if !forced => return None,
+        Some(_) => return Some(ColorDepth::Ansi16),
+        // most terminals without a terminfo entry still handle 256 colors
+        None => return Some(ColorDepth::Ansi256),
+    };
+}
+
+/// Returns the number of colors the terminfo entry of a terminal lists
+///
+/// # Arguments
+///
+/// * 'term'      - Terminal name, the value of TERM
+fn terminfo_colors(term: &str) -> Option<u32> {
+    let first: char = term.chars().next()?;
+    if term.contains('/') {
+        return None;
+    }
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    if let Ok(dir) = env::var("TERMINFO") {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Ok(home) = env::var("HOME") {
+        dirs.push(PathBuf::from(home).join(".terminfo"));
+    }
+    if let Ok(list) = env::var("TERMINFO_DIRS") {
+        dirs.extend(list.split(':').filter(|dir| !dir.is_empty()).map(PathBuf::from));
+    }
+    dirs.extend(["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo"].map(PathBuf::from));
+    for dir in dirs {
+        // entries are filed under their first letter, or its hex code on macOS
+        for subdir in [first.to_string(), format!("{:x}", first as u32)] {
+            if let Ok(entry) = fs::read(dir.join(subdir).join(term)) {
+                return parse_terminfo_colors(&entry);
+            }
+        }
+    }
+    return None;
+}
+
+/// Reads the max_colors number of a compiled terminfo entry, in either the legacy format with
+/// 16 bit numbers or the extended one with 32 bit numbers
+///
+/// # Arguments
+///
+/// * 'entry'     - Contents of the terminfo file
+fn parse_terminfo_colors(entry: &[u8]) -> Option<u32> {
+    let short = |offset: usize| -> Option<i16> {
+        return Some(i16::from_le_bytes([*entry.get(offset)?, *entry.get(offset + 1)?]));
+    };
+    let number_size: usize = match short(0)? {
+        0o432 => 2,
+        0o1036 => 4,
+        _ => return None,
+    };
+    let names: usize = usize::try_from(short(2)?).ok()?;
+    let booleans: usize = usize::try_from(short(4)?).ok()?;
+    let numbers: usize = usize::try_from(short(6)?).ok()?;
+    if numbers <= TERMINFO_COLORS {
+        return None;
+    }
+    // the numbers section starts on an even byte
+    let start: usize = (12 + names + booleans).next_multiple_of(2) + TERMINFO_COLORS * number_size;
+    let colors: i32 = match number_size {
+        2 => short(start)? as i32,
+        _ => i32::from_le_bytes(entry.get(start..start + 4)?.try_into().ok()?),
+    };
+    return u32::try_from(colors).ok();
+}
+
+/// Puts the terminal into a state for playing frames in place: the alternate screen with the
+/// cursor hidden, and Ctrl-C caught so the terminal can be restored. The previous state comes
+/// back when it's dropped.
+pub struct Playback<W: Write> {
+    out: W,
+    #[cfg(unix)]
+    previous_handler: libc::sighandler_t,
+}
+
+impl<W: Write> Playback<W> {
+    /// Switches the terminal to playback
+    ///
+    /// # Arguments
+    ///
+    /// * 'out'       - Where frames are written, e.g. stdout
+    pub fn start(mut out: W) -> io::Result<Playback<W>> {
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        out.write_all(ENTER_PLAYBACK.as_bytes())?;
+        out.flush()?;
+        return Ok(Playback {
+            out,
+            #[cfg(unix)]
+            // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+            previous_handler: unsafe { libc::signal(libc::SIGINT, on_interrupt as *const () as libc::sighandler_t) },
+        });
+    }
+
+    /// Writes a frame over the previous one, the terminal shows it all at once
+    ///
+    /// # Arguments
+    ///
+    /// * 'draw'      - Writes the frame
+    pub fn frame<F, E>(&mut self, draw: F) -> Result<(), E>
+    where
+        F: FnOnce(&mut W) -> Result<(), E>,
+        E: From<io::Error>,
+    {
+        self.out.write_all(BEGIN_FRAME.as_bytes())?;
+        draw(&mut self.out)?;
+        self.out.write_all(END_FRAME.as_bytes())?;
+        self.out.flush()?;
+        return Ok(());
+    }
+
+    /// Waits for the delay of a frame, returns false when playback was interrupted
+    ///
+    /// # Arguments
+    ///
+    /// * 'delay'     - How long the frame is shown for
+    pub fn wait(&self, delay: Duration) -> bool {
+        let end: Instant = Instant::now() + delay;
+        while !INTERRUPTED.load(Ordering::SeqCst) {
+            let now: Instant = Instant::now();
+            if now >= end {
+                return true;
+            }
+            thread::sleep((end - now).min(Duration::from_millis(INTERRUPT_POLL_MS)));
+        }
+        return false;
+    }
+}
+
+impl<W: Write> Drop for Playback<W> {
+    fn drop(&mut self) {
+        // nothing is left to report a failed restore to while dropping
+        let _ = self.out.write_all(LEAVE_PLAYBACK.as_bytes()).and_then(|_| self.out.flush());
+        #[cfg(unix)]
+        // SAFETY: puts back the handler that was replaced in start
+        unsafe {
+            libc::signal(libc::SIGINT, self.previous_handler)
+        };
+    }
+}
+
+/// Records a Ctrl-C during playback
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Returns the size of the terminal in columns and rows, queried from the terminal when
+/// possible and read from the COLUMNS and LINES environment variables otherwise
+pub fn terminal_size() -> Option<(u32, u32)> {
+    return query_size().or_else(env_size);
+}
+
+/// Returns the columns and rows output can fill in a terminal of the given size, keeping the
+/// last row free for the prompt shown after it
+///
+/// # Arguments
+///
+/// * 'size'    - Number of columns and rows of the terminal
+pub fn fit_area(size: (u32, u32)) -> (u32, u32) {
+    let (columns, rows) = size;
+    return (columns, rows.saturating_sub(1).max(1));
+}
+
+/// Returns the window size reported by the terminal attached to stdout, stderr or stdin
+#[cfg(unix)]
+fn query_size() -> Option<(u32, u32)> {
+    for fd in [libc::STDOUT_FILENO, libc::STDERR_FILENO, libc::STDIN_FILENO] {
+        let mut size: libc::winsize = libc::winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+        // SAFETY: TIOCGWINSZ only writes a winsize struct through the given pointer
+        let result: libc::c_int = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) };
+        if result == 0 && size.ws_col > 0 && size.ws_row > 0 {
+            return Some((size.ws_col as u32, size.ws_row as u32));
+        }
+    }
+    return None;
+}
+
+/// Returns the window size reported by the terminal attached to stdout, stderr or stdin
+#[cfg(not(unix))]
+fn query_size() -> Option<(u32, u32)> {
+    return None;
+}
+
+/// Returns the terminal size set by the shell in the COLUMNS and LINES variables
+fn env_size() -> Option<(u32, u32)> {
+    let dimension = |name: &str| -> Option<u32> {
+        return env::var(name).ok()?.trim().parse::<u32>().ok().filter(|value| *value > 0);
+    };
+    return Some((dimension("COLUMNS")?, dimension("LINES")?));
+}
+
+/// Returns true if a color is light enough for dark text to be drawn on it
+///
+/// # Arguments
+///
+/// * 'rgb'       - Background color
+pub fn is_light(rgb: (u8, u8, u8)) -> bool {
+    let luma: f64 = 0.2126 * rgb.0 as f64 + 0.7152 * rgb.1 as f64 + 0.0722 * rgb.2 as f64;
+    return luma > 127.5;
+}
+
+/// Returns whether the terminal has a light background, asking the terminal for its background
+/// color (OSC 11) and falling back to the COLORFGBG variable. None when it can't be told.
+pub fn light_background() -> Option<bool> {
+    return query_background().map(is_light).or_else(env_background);
+}
+
+/// Returns the light or dark background set in COLORFGBG, which holds the palette indices of
+/// the foreground and background colors separated by semicolons
+fn env_background() -> Option<bool> {
+    let colors: String = env::var("COLORFGBG").ok()?;
+    let background: u8 = colors.rsplit(';').next()?.trim().parse::<u8>().ok()?;
+    // white and the bright colors, except for bright black
+    return Some(background == 7 || (9..=15).contains(&background));
+}
+
+/// Parses the answer to an OSC 11 query, e.g. `ESC ] 11 ; rgb:ffff/ffff/dddd BEL`
+///
+/// # Arguments
+///
+/// * 'response'  - Bytes the terminal answered with
+fn parse_background(response: &str) -> Option<(u8, u8, u8)> {
+    let start: usize = response.find("]11;rgb:")? + "]11;rgb:".len();
+    let end: usize = response[start..]
+        .find(|c: char| !c.is_ascii_hexdigit() && c != '/')
+        .map_or(response.len(), |end| start + end);
+    let channels: Vec<u8> = response[start..end]
+        .split('/')
+        .map(|channel| {
+            // channels have 1 - 4 hex digits, scale them to 8 bits
+            let max: f64 = (16_u32.pow(channel.len().clamp(1, 4) as u32) - 1) as f64;
+            let value: u32 = u32::from_str_radix(channel, 16).ok()?;
+            return Some((value as f64 / max * 255.0).round() as u8);
+        })
+        .collect::<Option<Vec<u8>>>()?;
+    match channels[..] {
+        [r, g, b] => return Some((r, g, b)),
+        _ => return None,
+    };
+}
+
+/// Asks the terminal for its background color with an OSC 11 query. A device attributes query
+/// is sent after it, which every terminal answers, so terminals that ignore OSC 11 don't make
+/// us wait for the timeout.
+#[cfg(unix)]
+fn query_background() -> Option<(u8, u8, u8)> {
+    // SAFETY: isatty only inspects the file descriptor
+    if unsafe { libc::isatty(libc::STDOUT_FILENO) } != 1 {
+        return None;
+    }
+    let mut tty: File = OpenOptions::new().read(true).write(true).open("/dev/tty").ok()?;
+    let fd: libc::c_int = tty.as_raw_fd();
+    // background jobs get stopped when they touch the terminal's settings or read from it
+    // SAFETY: tcgetpgrp and getpgrp only return process group ids
+    if unsafe { libc::tcgetpgrp(fd) != libc::getpgrp() } {
+        return None;
+    }
+    // SAFETY: termios is plain data filled in by tcgetattr
+    let mut original: libc::termios = unsafe { std::mem::zeroed() };
+    // SAFETY: tcgetattr only writes the termios struct passed to it
+    if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+        return None;
+    }
+    // read the answer without waiting for a newline and without echoing it
+    let mut raw: libc::termios = original;
+    raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+    raw.c_cc[libc::VMIN] = 0;
+    raw.c_cc[libc::VTIME] = 0;
+    // SAFETY: tcsetattr only reads the termios struct passed to it
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+        return None;
+    }
+
+    let mut response: Vec<u8> = Vec::new();
+    if tty.write_all(b"\x1b]11;?\x1b\\\x1b[c").and_then(|_| tty.flush()).is_ok() {
+        let mut buffer: [u8; 64] = [0; 64];
+        loop {
+            let mut poll: libc::pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+            // SAFETY: poll reads and writes a single pollfd
+            if unsafe { libc::poll(&mut poll, 1, QUERY_TIMEOUT_MS) } <= 0 {
+                break;
+            }
+            match tty.read(&mut buffer) {
+                Ok(read) if read > 0 => response.extend_from_slice(&buffer[..read]),
+                _ => break,
+            };
+            // the device attributes answer ends with 'c' and comes last
+            if let Some(start) = response.windows(3).position(|window| window == b"\x1b[?") {
+                if response[start..].contains(&b'c') {
+                    break;
+                }
+            }
+        }
+    }
+    // a failed restore is ignored, the answer is still usable and there's no other state to
+    // fall back to; it can only fail if the terminal went away while reading
+    // SAFETY: tcsetattr only reads the termios struct filled in by tcgetattr above
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+    return parse_background(&String::from_utf8_lossy(&response));
+}
+
+/// Asks the terminal for its background color
+#[cfg(not(unix))]
+fn query_background() -> Option<(u8, u8, u8)> {
+    return None;
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn background_responses() {
+        assert_eq!(Some((255, 255, 221)), parse_background("\x1b]11;rgb:ffff/ffff/dddd\x07\x1b[?62;c"));
+        assert_eq!(Some((0, 43, 54)), parse_background("\x1b]11;rgb:00/2b/36\x1b\\"));
+        assert_eq!(None, parse_background("\x1b[?1;2c"));
+        assert!(is_light((255, 255, 221)));
+        assert!(!is_light((0, 43, 54)));
+    }
+
+    #[test]
+    fn color_detection() {
+        let detect = |vars: &[(&str, &str)], tty: bool, colors: Option<u32>| -> Option<ColorDepth> {
+            let var = |name: &str| -> Option<String> {
+                return vars.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string());
+            };
+            return detect_depth(var, tty, |_| colors);
+        };
+        assert_eq!(Some(ColorDepth::Truecolor), detect(&[("COLORTERM", "24bit")], true, None));
+        assert_eq!(Some(ColorDepth::Ansi256), detect(&[("TERM", "xterm-256color")], true, Some(8)));
+        assert_eq!(Some(ColorDepth::Ansi16), detect(&[("TERM", "xterm")], true, Some(8)));
+        assert_eq!(None, detect(&[("TERM", "vt100")], true, Some(0)));
+        assert_eq!(None, detect(&[("COLORTERM", "truecolor")], false, None));
+        assert_eq!(None, detect(&[("COLORTERM", "truecolor"), ("NO_COLOR", "1")], true, None));
+        assert_eq!(
+            Some(ColorDepth::Truecolor),
+            detect(&[("COLORTERM", "truecolor"), ("CLICOLOR_FORCE", "1")], false, None)
+        );
+        assert_eq!(None, detect(&[("CLICOLOR_FORCE", "0")], false, None));
+        assert_eq!(Some(ColorDepth::Ansi16), color_depth(ColorMode::Ansi16));
+        assert_eq!(None, color_depth(ColorMode::Never));
+    }
+
+    #[test]
+    fn playback_frames() {
+        #[cfg(unix)]
+        // SAFETY: only reads the current handler back by swapping it out and in again
+        let handler: libc::sighandler_t = unsafe {
+            let handler: libc::sighandler_t = libc::signal(libc::SIGINT, libc::SIG_DFL);
+            libc::signal(libc::SIGINT, handler);
+            handler
+        };
+        let mut out: Vec<u8> = Vec::new();
+        let mut playback: Playback<&mut Vec<u8>> = Playback::start(&mut out).unwrap();
+        playback.frame(|out| -> io::Result<()> { return out.write_all(b"@@\n") }).unwrap();
+        assert!(playback.wait(Duration::from_millis(1)));
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        assert!(!playback.wait(Duration::from_secs(10)));
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        drop(playback);
+
+        // dropping restores the terminal and the previous Ctrl-C handler
+        let expected: String = format!("{}{}@@\n{}{}", ENTER_PLAYBACK, BEGIN_FRAME, END_FRAME, LEAVE_PLAYBACK);
+        assert_eq!(expected.as_bytes(), &out[..]);
+        #[cfg(unix)]
+        // SAFETY: as above
+        unsafe {
+            assert_eq!(handler, libc::signal(libc::SIGINT, handler));
+        };
+    }
+
+    #[test]
+    fn terminfo_entries() {
+        // legacy header: magic, name, boolean, number and string counts, string table size
+        let mut entry: Vec<u8> = Vec::new();
+        for value in [0o432_i16, 6, 1, 15, 0, 0] {
+            entry.extend_from_slice(&value.to_le_bytes());
+        }
+        entry.extend_from_slice(b"term|\0\x01\0");
+        for index in 0..15_i16 {
+            let value: i16 = if index as usize == TERMINFO_COLORS { 256 } else { -1 };
+            entry.extend_from_slice(&value.to_le_bytes());
+        }
+        assert_eq!(Some(256), parse_terminfo_colors(&entry));
+
+        // extended header with 32 bit numbers
+        let mut entry: Vec<u8> = Vec::new();
+        for value in [0o1036_i16, 5, 0, 14, 0, 0] {
+            entry.extend_from_slice(&value.to_le_bytes());
+        }
+        entry.extend_from_slice(b"term\0\0");
+        for index in 0..14_i32 {
+            let value: i32 = if index as usize == TERMINFO_COLORS { 1 << 24 } else { -1 };
+            entry.extend_from_slice(&value.to_le_bytes());
+        }
+        assert_eq!(Some(1 << 24), parse_terminfo_colors(&entry));
+        assert_eq!(None, parse_terminfo_colors(b"not a terminfo file"));
+    }
+}
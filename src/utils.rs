@@ -1,13 +1,27 @@
+use crate::adjust::Equalize;
+use crate::blocks::BlockMode;
+use crate::cell::{CellStat, ColorTarget};
+use crate::dither::Dither;
+use crate::edges::EdgeDetector;
+use crate::error::AsciifyError;
+use crate::glyph_match::ShapeMetric;
+use crate::luminance::Luminance;
+use crate::renderer::DEFAULT_CELL_SIZE;
+use crate::resample::Filter;
+use crate::terminal::{ColorMode, Fit};
 use clap::{ Parser };
-use std::env;
+use image::ImageFormat;
+use std::path::Path;
+
+// file argument used to read image data from stdin
+pub const STDIN_ARG: &str = "-";
 
 #[derive(Debug, Default, Parser)]
 #[command(author="Joe Adamson")] 
 #[command(version = "1.0")]
 /// Create cool ASCII images from jpg, png and gif files!
- 
 pub struct AsciiArgs {
-    /// File(s) to be converted into ascii art
+    /// File(s) to be converted into ascii art, use - to read image data from stdin
     pub files: Vec<String>,
 
     /// Save ascii output to a either a png or gif format depending on the format
@@ -15,10 +29,28 @@ pub struct AsciiArgs {
     #[arg(long)]
     pub save: bool,
 
+    /// Write the image saved with --save to stdout instead of a file
+    #[arg(long, requires = "save")]
+    pub stdout: bool,
+
+    /// Save animated output as an APNG, which keeps full color, instead of a 256 color gif
+    #[arg(long, requires = "save")]
+    pub apng: bool,
+
     /// Print color ascii image(s) to the terminal
     #[arg(long, short)]
     pub color: bool,
 
+    /// Number of colors color output is printed with. auto detects it from the terminal and
+    /// prints without color when piped or when NO_COLOR is set, never always prints without
+    #[arg(long, value_enum, default_value_t = ColorMode::Auto)]
+    pub color_mode: ColorMode,
+
+    /// Color the characters (fg), fill their cells with the color and draw the characters
+    /// in black or white (bg), or fill the cells and draw the characters in a shade of it (both)
+    #[arg(long, value_enum, default_value_t = ColorTarget::default())]
+    pub color_target: ColorTarget,
+
     /// Choose to print image composed of a wider array of ascii characters
     /// (70 as opposed to default of 10)
     #[arg(long, short)]
@@ -30,7 +62,144 @@ pub struct AsciiArgs {
 
     /// Scale factor for output image
     #[arg(long, short)]
-    pub scale_factor: Option<u32>
+    pub scale_factor: Option<u32>,
+
+    /// Scale output to fill the width, height or both dimensions of the terminal
+    #[arg(long, value_enum, conflicts_with_all = ["scale_factor", "width", "height"])]
+    pub fit: Option<Fit>,
+
+    /// Number of characters in each row, the rows follow from the aspect ratio unless
+    /// --height is set as well
+    #[arg(long, conflicts_with = "scale_factor")]
+    pub width: Option<u32>,
+
+    /// Number of rows, the row length follows from the aspect ratio unless --width is
+    /// set as well
+    #[arg(long, conflicts_with = "scale_factor")]
+    pub height: Option<u32>,
+
+    /// Fill --width and --height (or the terminal with --fit both) exactly instead of
+    /// keeping the aspect ratio
+    #[arg(long)]
+    pub stretch: bool,
+
+    /// Filter used to scale the image down, defaults to nearest for pixel art and
+    /// gaussian otherwise
+    #[arg(long, value_enum)]
+    pub filter: Option<Filter>,
+
+    /// Sharpen the scaled image with an unsharp mask of the given radius in pixels
+    #[arg(long, value_parser = parse_positive)]
+    pub sharpen: Option<f64>,
+
+    /// Offset added to the brightness of the scaled image, -1.0 - 1.0
+    #[arg(long, allow_negative_numbers = true)]
+    pub brightness: Option<f64>,
+
+    /// Factor the contrast of the scaled image is scaled by
+    #[arg(long)]
+    pub contrast: Option<f64>,
+
+    /// Gamma correction of the scaled image, values above 1.0 brighten the mid tones
+    #[arg(long, value_parser = parse_positive)]
+    pub gamma: Option<f64>,
+
+    /// Stretch the intensities of the image to cover black to white
+    #[arg(long)]
+    pub auto_levels: bool,
+
+    /// Equalize the histogram of the image, globally or per region (clahe)
+    #[arg(long, value_enum)]
+    pub equalize: Option<Equalize>,
+
+    /// Height of a character divided by its width, defaults to --cell-height / --cell-width
+    #[arg(long, value_parser = parse_positive)]
+    pub char_aspect: Option<f64>,
+
+    /// Model used to compute pixel intensity from color
+    #[arg(long, value_enum, default_value_t = Luminance::default())]
+    pub luminance: Luminance,
+
+    /// Statistic used to reduce the pixels covered by each character
+    #[arg(long, value_enum, default_value_t = CellStat::default())]
+    pub cell_stat: CellStat,
+
+    /// Width in pixels of the scaled image covered by each character
+    #[arg(long, default_value_t = DEFAULT_CELL_SIZE.0)]
+    pub cell_width: u32,
+
+    /// Height in pixels of the scaled image covered by each character
+    #[arg(long, default_value_t = DEFAULT_CELL_SIZE.1)]
+    pub cell_height: u32,
+
+    /// Dither intensities across the character mapping (and colors across the
+    /// 256 or 16 color palette when truecolor isn't supported)
+    #[arg(long, value_enum, default_value_t = Dither::default())]
+    pub dither: Dither,
+
+    /// Dither colors across the 256 or 16 color palette, defaults to --dither
+    #[arg(long, value_enum)]
+    pub color_dither: Option<Dither>,
+
+    /// Draw strong edges with direction matching characters, using the given edge detector
+    #[arg(long, value_enum, conflicts_with = "blocks")]
+    pub edges: Option<EdgeDetector>,
+
+    /// Pick characters by matching the shape of their glyphs against the image
+    #[arg(long, value_enum, conflicts_with = "blocks")]
+    pub shape: Option<ShapeMetric>,
+
+    /// Font file used to match glyph shapes and draw saved output
+    #[arg(long)]
+    pub font: Option<String>,
+
+    /// Draw with unicode block characters, showing several pixels per character
+    #[arg(long, value_enum)]
+    pub blocks: Option<BlockMode>,
+
+    /// Reverse the character ramp. The ramp is already reversed on terminals with a light
+    /// background and when saving onto a light --canvas, --invert flips it back.
+    #[arg(long)]
+    pub invert: bool,
+
+    /// Background color of saved output as a hex code, e.g. ffffff or #1e1e2e
+    #[arg(long, value_parser = parse_color, default_value = "000000")]
+    pub canvas: (u8, u8, u8),
+
+    /// Color transparent pixels are blended over as a hex code, defaults to the --canvas
+    /// color when saving and to black or white matching the terminal otherwise
+    #[arg(long, value_parser = parse_color)]
+    pub background: Option<(u8, u8, u8)>,
+
+    /// Print mostly transparent cells as uncolored spaces so they blend into any terminal theme
+    #[arg(long)]
+    pub transparent: bool,
+}
+
+/// Parses a ratio that must be a positive number, e.g. a character aspect ratio
+///
+/// # Arguments
+///
+/// * 'arg'       - Commandline value
+fn parse_positive(arg: &str) -> Result<f64, String> {
+    match arg.parse::<f64>() {
+        Ok(ratio) if ratio.is_finite() && ratio > 0.0 => return Ok(ratio),
+        _ => return Err(format!("{} is not a positive number", arg)),
+    };
+}
+
+/// Parses a hex color code with or without a leading #, e.g. #ff8800
+///
+/// # Arguments
+///
+/// * 'arg'       - Commandline value
+pub fn parse_color(arg: &str) -> Result<(u8, u8, u8), String> {
+    let hex: &str = arg.trim_start_matches('#');
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("{} is not a hex color code like #ff8800", arg));
+    }
+    let channel = |i: usize| -> u8 { return u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or(0) };
+    return Ok((channel(0), channel(2), channel(4)));
 }
 
 // encode the dimensions of the original image the pixel belongs to
@@ -39,6 +208,10 @@ pub struct AsciiArgs {
 pub struct AsciiToken {
     pub token: char,
     pub rgb: (u8, u8, u8),
+    // background color, only set for block characters
+    pub bg_rgb: Option<(u8, u8, u8)>,
+    // transparent cells are printed as spaces without color
+    pub transparent: bool,
     pub parent_img_width: u32,
     pub parent_img_height: u32
 }
@@ -50,59 +223,133 @@ pub struct AsciiFrame {
 }
 
 
+/// Returns the number of columns and rows of a grid of tokens
+/// 
+/// # Arguments
+/// 
+/// * 'tokens'    - Ascii tokens with rows separated by newline tokens
+pub fn grid_dimensions(tokens: &[AsciiToken]) -> (usize, usize) {
+    let cols: usize = tokens.iter().take_while(|token| token.token != '\n').count();
+    let rows: usize = tokens.iter().filter(|token| token.token == '\n').count();
+    return (cols, rows);
+}
+
 /// Returns file extension for a given file
 /// 
 /// # Arguments
 /// 
 /// * 'path_arg'  - file path
 pub fn get_file_extension(file_path: &String) -> Option<&str> {
-    let tokens: Vec<&str> = file_path.split(".").collect();
-    if tokens.len() == 1 {
-        return None;
-    }
-    return Some(tokens[tokens.len() - 1]);
+    return Path::new(file_path).extension().and_then(|ext| ext.to_str());
 }
 
-/// Returns file name for saveed data
+/// Returns file name for saveed data, the original extension is kept when it matches the
+/// output format
 /// 
 /// * 'path_arg'  - file path
-pub fn build_output_file_name(file_path: &String) -> Result<String, &'static str> {
-    let dirs: Vec<&str> = file_path.split("/").collect();
-    let tokens: Vec<&str> = dirs[dirs.len() -1].split(".").collect();
-    if tokens.len() < 2 {
-        return Err("Not a parsable file!");
-    } else {
-        return Ok(format!("asciify-{}.{}", tokens[0], tokens[1]));
+/// * 'format'    - format the output will be encoded in
+pub fn build_output_file_name(file_path: &String, format: ImageFormat) -> Result<String, AsciifyError> {
+    let stem: &str = match Path::new(file_path).file_stem().and_then(|stem| stem.to_str()) {
+        Some(stem) => stem,
+        None => return Err(AsciifyError::UnsupportedFormat(format!("{} is not a file", file_path))),
+    };
+    let ext: &str = match get_file_extension(file_path) {
+        Some(ext) if ImageFormat::from_extension(ext) == Some(format) => ext,
+        _ => format.extensions_str()[0],
+    };
+    return Ok(format!("asciify-{}.{}", stem, ext));
+}
+
+/// Returns true if asciify can convert images of the given format
+/// 
+/// # Arguments
+/// 
+/// * 'format'    - image format
+pub fn is_convertible(format: ImageFormat) -> bool {
+    match format {
+        ImageFormat::Png
+        | ImageFormat::Jpeg
+        | ImageFormat::Gif
+        | ImageFormat::WebP
+        | ImageFormat::Pnm
+        | ImageFormat::Tiff
+        | ImageFormat::Tga
+        | ImageFormat::Bmp
+        | ImageFormat::Ico
+        | ImageFormat::Hdr
+        | ImageFormat::OpenExr
+        | ImageFormat::Farbfeld
+        | ImageFormat::Qoi => return true,
+        _ => return false,
+    };
+}
+
+/// Returns the format asciified output is saved in for a given input format
+/// 
+/// # Arguments
+/// 
+/// * 'input'     - format of the original file
+/// * 'animated'  - the original file held more than one frame, animated png output is
+///   written as an apng
+pub fn output_format(input: ImageFormat, animated: bool) -> ImageFormat {
+    if animated {
+        // keep full color for animations that weren't limited to a gif palette to begin with
+        if input == ImageFormat::Png || input == ImageFormat::WebP {
+            return ImageFormat::Png;
+        }
+        return ImageFormat::Gif;
     }
+    match input {
+        ImageFormat::Jpeg
+        | ImageFormat::Bmp
+        | ImageFormat::Tiff
+        | ImageFormat::Tga
+        | ImageFormat::Qoi
+        | ImageFormat::Farbfeld => return input,
+        // webp, ico, pnm and the float formats either can't be written here or can't
+        // hold the rendered rgba canvas, so fall back to png
+        _ => return ImageFormat::Png,
+    };
 }
 
-/// Check the format of a given file is parsable
+/// Check the extension of a given file names a parsable format, detect_format falls back
+/// on it when the contents aren't recognised
 /// 
 /// # Arguments
 /// 
 /// * 'parth_arg' - file path   
 pub fn is_supported_format(file_path: &String) -> bool {
-    let last: &str = get_file_extension(file_path).expect("Could not parse file extension");
-    if last == "jpg" || last == "jpeg" || last == "png" || last == "gif" {
-        return true;
-    } 
-    return false;
+    match get_file_extension(file_path).and_then(ImageFormat::from_extension) {
+        Some(format) => return is_convertible(format),
+        None => return false,
+    };
 }
 
-/// Returns true if terminal application caller supports truecolor (16 million colors)
-pub fn supports_truecolor() -> bool {
-    let key: &str = "COLORTERM";
-    let val: String = match env::var(key) {
-        Ok(colorterm) => colorterm,
-        Err(_) => String::from("none")
+/// Detects the format of encoded image data from its magic bytes, falling back to the
+/// extension of the file it was read from
+/// 
+/// # Arguments
+/// 
+/// * 'bytes'     - leading bytes of the file
+/// * 'path_hint' - path the data was read from, if any
+pub fn detect_format(bytes: &[u8], path_hint: Option<&String>) -> Result<ImageFormat, AsciifyError> {
+    let format: Option<ImageFormat> = match image::guess_format(bytes) {
+        Ok(format) => Some(format),
+        // the extension only counts when it names a format asciify converts
+        Err(_) => match path_hint {
+            Some(path) if is_supported_format(path) => {
+                get_file_extension(path).and_then(ImageFormat::from_extension)
+            }
+            _ => None,
+        },
+    };
+    match format {
+        Some(format) if is_convertible(format) => return Ok(format),
+        Some(format) => return Err(AsciifyError::UnsupportedFormat(format!("{:?} images", format))),
+        None => return Err(AsciifyError::UnsupportedFormat(String::from("unrecognised image data"))),
     };
-    if val != "truecolor" {
-        return false;
-    }
-    return true;
 }
 
-
 #[cfg(test)]
 mod test {
 
@@ -120,6 +367,14 @@ mod test {
         assert_ne!(true, is_supported_format(&dummy));
     }
 
+    #[test]
+    fn parse_color_test() {
+        assert_eq!(Ok((255, 136, 0)), parse_color("#ff8800"));
+        assert_eq!(Ok((30, 30, 46)), parse_color("1E1E2E"));
+        assert!(parse_color("#fff").is_err());
+        assert!(parse_color("white").is_err());
+    }
+
     #[test]
     fn get_file_extension_test() {
         let dummy: String = String::from("dummy_file22.gif");
@@ -136,17 +391,11 @@ mod test {
         assert_eq!(actual, expected);
     }
 
-    // #[test]
-    // fn supports_truecolor_test() {
-    //     let res: bool = supports_truecolor();
-    //     assert!(res);
-    // }
-
     #[test]
     fn build_file_name_test() {
         let dummy: String = String::from("ferris.png");
         let expected: String = String::from("asciify-ferris.png");
-        let actual: String = build_output_file_name(&dummy).unwrap();
+        let actual: String = build_output_file_name(&dummy, ImageFormat::Png).unwrap();
         assert_eq!(expected, actual);
     }
 
@@ -154,7 +403,62 @@ mod test {
     fn build_file_name_test_2() {
         let dummy: String = String::from("../../assets/mario.png");
         let expected: String = String::from("asciify-mario.png");
-        let actual: String = build_output_file_name(&dummy).unwrap();
+        let actual: String = build_output_file_name(&dummy, ImageFormat::Png).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn build_file_name_test_3() {
+        // extension is taken from the detected format when missing or mismatched
+        let dummy: String = String::from("./dir.v2/img");
+        let expected: String = String::from("asciify-img.gif");
+        let actual: String = build_output_file_name(&dummy, ImageFormat::Gif).unwrap();
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn get_file_extension_test_3() {
+        let dummy: String = String::from("./dir.v2/img");
+        assert_eq!(None, get_file_extension(&dummy));
+    }
+
+    #[test]
+    fn supported_format_test_3() {
+        let dummy: String = String::from("photo.JPG");
+        assert!(is_supported_format(&dummy));
+    }
+
+    #[test]
+    fn supported_format_test_4() {
+        for file in ["icon.ico", "photo.webp", "scan.tiff", "old.bmp", "sprite.tga", "x.qoi", "y.ppm"] {
+            assert!(is_supported_format(&String::from(file)), "{} should be supported", file);
+        }
+    }
+
+    #[test]
+    fn output_format_test() {
+        assert_eq!(ImageFormat::Jpeg, output_format(ImageFormat::Jpeg, false));
+        assert_eq!(ImageFormat::Png, output_format(ImageFormat::WebP, false));
+        assert_eq!(ImageFormat::Png, output_format(ImageFormat::Ico, false));
+        assert_eq!(ImageFormat::Gif, output_format(ImageFormat::Tiff, true));
+        assert_eq!(ImageFormat::Png, output_format(ImageFormat::WebP, true));
+    }
+
+    #[test]
+    fn detect_format_test() {
+        // contents win over a misleading extension
+        let dummy: String = String::from("assets/ferris.png");
+        let bytes: Vec<u8> = std::fs::read("assets/ferris.jpg").unwrap();
+        let actual: ImageFormat = detect_format(&bytes, Some(&dummy)).unwrap();
+        assert_eq!(ImageFormat::Jpeg, actual);
+    }
+
+    #[test]
+    fn detect_format_test_2() {
+        // fall back to the extension when the contents are not recognised
+        let dummy: String = String::from("unknown.png");
+        let actual: ImageFormat = detect_format(&[0, 1, 2, 3], Some(&dummy)).unwrap();
+        assert_eq!(ImageFormat::Png, actual);
+        assert!(detect_format(&[0, 1, 2, 3], None).is_err());
+    }
 }
\ No newline at end of file